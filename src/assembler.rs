@@ -1,19 +1,66 @@
 use opcodes::Opcode;
+use debugger::DebugInfo;
+use vm::TokenAction;
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
 
 
 pub struct Assembler {
     buffer: Vec<u32>,
     cp_buffer: Vec<u32>,
-    cp_value_to_index: HashMap<u32, u16>
+    cp_value_to_index: HashMap<u32, PoolIndex>,
+    /// First encoding error, it is reported from `finish`
+    error: Option<EncodingError>
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingError {
+    /// Code pointer doesn't fit into operand of the instruction
+    CodePointerOverflow { opcode: Opcode, pointer: usize, limit: usize },
+    /// Constant pool index doesn't fit into operand of the instruction
+    PoolIndexOverflow { opcode: Opcode, index: usize, limit: usize },
+    /// Numeric operand doesn't fit into the instruction
+    OperandOverflow { opcode: Opcode, value: usize, limit: usize },
+    /// Program has more instructions, than `CodePointer` can address
+    CodeTooLarge { limit: usize },
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            EncodingError::CodePointerOverflow { opcode, pointer, limit } =>
+                write!(f, "code pointer {} exceeds limit {} of {:?}", pointer, limit, opcode),
+            EncodingError::PoolIndexOverflow { opcode, index, limit } =>
+                write!(f, "constant pool index {} exceeds limit {} of {:?}", index, limit, opcode),
+            EncodingError::OperandOverflow { opcode, value, limit } =>
+                write!(f, "operand {} exceeds limit {} of {:?}", value, limit, opcode),
+            EncodingError::CodeTooLarge { limit } =>
+                write!(f, "program exceeds limit of {} instructions", limit),
+        }
+    }
+}
+
+impl Error for EncodingError {}
+
 fn is_valid_inline_code_point(code_point: u32) -> bool {
-    return code_point & (0b1111 << 28) == 0;
+    code_point & (0b1111 << 28) == 0
+}
+
+/// Max value of 14 bit operand of binary instruction
+const MAX_BINARY_OPERAND: u32 = 0b11_1111_1111_1111;
+/// Max value of 28 bit payload of unary instruction
+const MAX_PAYLOAD: u32 = !(0b1111 << 28);
+
+fn is_valid_inline_range(from: char, to: char) -> bool {
+    from as u32 <= MAX_BINARY_OPERAND && to as u32 <= MAX_BINARY_OPERAND
 }
 
-pub type CodePointer = u16;
-pub type PoolIndex = u16;
+/// Code pointers are limited by 28 bit payload of Jmp and wide instructions
+pub type CodePointer = u32;
+/// Pool indices are limited by 28 bit payload of CharCp and wide instructions
+pub type PoolIndex = u32;
 
 pub struct PatchMarker {
     position: CodePointer,
@@ -24,45 +71,142 @@ pub struct SplitManyMarker {
     position: CodePointer
 }
 
+pub struct JmpMarker {
+    position: CodePointer
+}
+
+pub struct OuterLexerMarker {
+    position: CodePointer
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new()
+    }
+}
+
 impl Assembler {
     pub fn new() -> Self {
         Assembler {
             buffer: Vec::new(),
             cp_buffer: Vec::new(),
-            cp_value_to_index: HashMap::new()
+            cp_value_to_index: HashMap::new(),
+            error: None
         }
     }
 
     pub fn emit_instr(&mut self, opcode: Opcode, payload: u32) {
+        if self.buffer.len() > MAX_PAYLOAD as usize {
+            self.report(EncodingError::CodeTooLarge { limit: MAX_PAYLOAD as usize + 1 });
+        }
         self.buffer.push(((opcode as u32) << 28) | payload)
     }
 
+    /// Emits char check, chooses between inline and constant pool forms
+    pub fn emit_char(&mut self, ch: char) {
+        if is_valid_inline_code_point(ch as u32) {
+            self.emit_char_imm(ch)
+        } else {
+            self.emit_char_cp(ch)
+        }
+    }
+
     pub fn emit_char_imm(&mut self, ch: char) {
         self.emit_instr(Opcode::CharImm, ch as u32)
     }
 
     pub fn emit_char_cp(&mut self, ch: char) {
-        let pool_index = self.get_pool_index(ch as u32);
-        self.emit_instr(Opcode::CharCp, pool_index as u32)
+        let pool_index = self.get_pool_index(Opcode::CharCp, ch as u32, MAX_PAYLOAD);
+        self.emit_instr(Opcode::CharCp, pool_index)
     }
 
     pub fn emit_match(&mut self, token_type_index: u16) {
         self.emit_instr(Opcode::Match, token_type_index as u32)
     }
 
+    /// Emits range check, chooses between inline and constant pool forms
+    pub fn emit_range(&mut self, from: char, to: char) {
+        if is_valid_inline_range(from, to) {
+            self.emit_range_imm(from, to)
+        } else {
+            self.emit_range_cp(from, to)
+        }
+    }
+
+    /// Emits range with inline bounds, bounds beyond 14 bits are reported as `OperandOverflow`, see `emit_range`
     pub fn emit_range_imm(&mut self, from: char, to: char) {
-        // TODO assert, that from and to fits into 14 bits
-        self.emit_binary_instr(Opcode::RangeImm, from as u16, to as u16)
+        if !is_valid_inline_range(from, to) {
+            let value = from.max(to) as usize;
+            self.report(EncodingError::OperandOverflow { opcode: Opcode::RangeImm, value, limit: MAX_BINARY_OPERAND as usize });
+        }
+        self.emit_binary_instr(Opcode::RangeImm, from as u32, to as u32)
+    }
+
+    /// Emits range, which bounds are stored in constant pool.
+    /// When pool indices of bounds don't fit into 14 bits, bounds are stored as a pair and RangeWide is used
+    pub fn emit_range_cp(&mut self, from: char, to: char) {
+        let (from, to) = (from as u32, to as u32);
+        // indices are predicted before touching the pool, so RangeWide doesn't leave unused bounds there
+        let mut next_index = self.cp_buffer.len() as PoolIndex;
+        let from_index = match self.cp_value_to_index.get(&from) {
+            Some(&index) => index,
+            None => {
+                next_index += 1;
+                next_index - 1
+            }
+        };
+        let to_index = match self.cp_value_to_index.get(&to) {
+            Some(&index) => index,
+            None if to == from => from_index,
+            None => next_index,
+        };
+        if from_index <= MAX_BINARY_OPERAND && to_index <= MAX_BINARY_OPERAND {
+            let from_index = self.get_pool_index(Opcode::Range, from, MAX_BINARY_OPERAND);
+            let to_index = self.get_pool_index(Opcode::Range, to, MAX_BINARY_OPERAND);
+            self.emit_binary_instr(Opcode::Range, from_index, to_index)
+        } else {
+            let pair_index = self.push_pool_pair(Opcode::RangeWide, from, to);
+            self.emit_instr(Opcode::RangeWide, pair_index)
+        }
     }
 
+    /// Emits Split, if targets don't fit into 14 bits, SplitWide with targets in constant pool is emitted
     pub fn emit_split(&mut self, then_instr_index: CodePointer, else_instr_index: CodePointer) -> (PatchMarker, PatchMarker) {
-        self.emit_binary_instr(Opcode::Split, then_instr_index, else_instr_index);
+        if then_instr_index <= MAX_BINARY_OPERAND && else_instr_index <= MAX_BINARY_OPERAND {
+            self.emit_binary_instr(Opcode::Split, then_instr_index, else_instr_index);
+        } else {
+            let pair_index = self.push_pool_pair(Opcode::SplitWide, then_instr_index, else_instr_index);
+            self.emit_instr(Opcode::SplitWide, pair_index);
+        }
         let position = self.last_code_position();
-        return (PatchMarker { position, is_first: false }, PatchMarker { position, is_first: true })
+        (PatchMarker { position, is_first: false }, PatchMarker { position, is_first: true })
+    }
+
+    pub fn emit_jmp(&mut self, instr_index: CodePointer) -> JmpMarker {
+        let marker = JmpMarker { position: self.next_code_position() };
+        self.check_code_pointer(Opcode::Jmp, instr_index, MAX_PAYLOAD);
+        self.emit_instr(Opcode::Jmp, instr_index & MAX_PAYLOAD);
+        marker
+    }
+
+    pub fn patch_jmp(&mut self, marker: &JmpMarker, new_pos: CodePointer) {
+        self.check_code_pointer(Opcode::Jmp, new_pos, MAX_PAYLOAD);
+        let position = marker.position as usize;
+        self.buffer[position] = (self.buffer[position] & !MAX_PAYLOAD) | (new_pos & MAX_PAYLOAD);
     }
 
-    pub fn emit_jmp(&mut self, instr_index: CodePointer) {
-        self.emit_instr(Opcode::Jmp, instr_index as u32)
+    pub fn emit_any(&mut self, matches_newline: bool) {
+        self.emit_instr(Opcode::Any, matches_newline as u32)
+    }
+
+    /// Emits prefix of the `count` following char and range instructions (class members),
+    /// thread passes it if char matches none of them
+    pub fn emit_invert(&mut self, count: usize) {
+        let limit = u16::MAX as usize;
+        if count > limit {
+            self.report(EncodingError::OperandOverflow { opcode: Opcode::Invert, value: count, limit });
+        }
+        self.emit_instr(Opcode::Invert, count as u32 & limit as u32)
     }
 
     pub fn emit_noop(&mut self) {
@@ -75,11 +219,30 @@ impl Assembler {
         table_marker
     }
 
+    /// Stores table into constant pool as its length followed by targets
     pub fn patch_split_many(&mut self, marker: &SplitManyMarker, table: Vec<CodePointer>) {
-        let table_index = self.cp_buffer.len();
-        let old_instruction = self.buffer[marker.position as usize];
-        self.buffer[marker.position as usize] = (old_instruction & (0b1111_1111_1111_1111 << 16)) | (table_index as u32);
-        self.cp_buffer.extend(table.iter().map(|el| *el as u32));
+        self.patch_table(Opcode::SplitMany, marker.position, table)
+    }
+
+    /// Emits dispatch to lexer modes, it must be the first instruction of the program
+    pub fn emit_outer_lexer(&mut self) -> OuterLexerMarker {
+        let marker = OuterLexerMarker { position: self.next_code_position() };
+        self.emit_instr(Opcode::OuterLexer, 0);
+        marker
+    }
+
+    /// Stores entry points of modes into constant pool as their count followed by entries
+    pub fn patch_outer_lexer(&mut self, marker: &OuterLexerMarker, entries: Vec<CodePointer>) {
+        self.patch_table(Opcode::OuterLexer, marker.position, entries)
+    }
+
+    fn patch_table(&mut self, opcode: Opcode, position: CodePointer, table: Vec<CodePointer>) {
+        let table_index = self.cp_buffer.len() as u32;
+        self.check_pool_index(opcode, table_index, MAX_PAYLOAD);
+        let old_instruction = self.buffer[position as usize];
+        self.buffer[position as usize] = (old_instruction & !MAX_PAYLOAD) | (table_index & MAX_PAYLOAD);
+        self.cp_buffer.push(table.len() as u32);
+        self.cp_buffer.extend(table);
     }
 
     pub fn next_code_position(&self) -> CodePointer {
@@ -90,45 +253,96 @@ impl Assembler {
         (self.buffer.len() - 1) as CodePointer
     }
 
+    /// Patches target of Split, converts it into SplitWide, if new target doesn't fit into 14 bits
     pub fn patch_target(&mut self, patch_marker: &PatchMarker, new_pos: CodePointer) {
-        let instruction = self.buffer[patch_marker.position as usize];
-        if patch_marker.is_first {
-            let mask = !0b11_1111_1111_1111;
-            let new_instruction = (instruction & mask) | new_pos as u32;
-            self.buffer[patch_marker.position as usize] = new_instruction;
-        } else {
-            let mask = !(0b11_1111_1111_1111 << 14);
-            let new_instruction = (instruction & mask) | ((new_pos as u32) << 14);
-            self.buffer[patch_marker.position as usize] = new_instruction;
+        let position = patch_marker.position as usize;
+        let instruction = self.buffer[position];
+        if Opcode::from_instruction(instruction) == Opcode::Split {
+            if new_pos <= MAX_BINARY_OPERAND {
+                if patch_marker.is_first {
+                    let mask = !MAX_BINARY_OPERAND;
+                    self.buffer[position] = (instruction & mask) | new_pos;
+                } else {
+                    let mask = !(MAX_BINARY_OPERAND << 14);
+                    self.buffer[position] = (instruction & mask) | (new_pos << 14);
+                }
+                return;
+            }
+            self.widen_split(position);
+        }
+        let pair_index = (self.buffer[position] & MAX_PAYLOAD) as usize;
+        let slot = if patch_marker.is_first { 1 } else { 0 };
+        self.cp_buffer[pair_index + slot] = new_pos;
+    }
+
+    /// Moves targets of Split at `position` into constant pool, turning it into SplitWide
+    fn widen_split(&mut self, position: usize) {
+        let payload = self.buffer[position] & MAX_PAYLOAD;
+        let then_instr_index = payload >> 14;
+        let else_instr_index = payload & MAX_BINARY_OPERAND;
+        let pair_index = self.push_pool_pair(Opcode::SplitWide, then_instr_index, else_instr_index);
+        self.buffer[position] = ((Opcode::SplitWide as u32) << 28) | pair_index;
+    }
+
+    /// Pushes pair of values, that is never shared, into constant pool, returns index of the first one
+    fn push_pool_pair(&mut self, opcode: Opcode, first: u32, second: u32) -> PoolIndex {
+        let pair_index = self.cp_buffer.len() as PoolIndex;
+        self.check_pool_index(opcode, pair_index, MAX_PAYLOAD);
+        self.cp_buffer.push(first);
+        self.cp_buffer.push(second);
+        pair_index & MAX_PAYLOAD
+    }
+
+    fn check_code_pointer(&mut self, opcode: Opcode, pointer: CodePointer, limit: u32) {
+        if pointer > limit {
+            self.report(EncodingError::CodePointerOverflow { opcode, pointer: pointer as usize, limit: limit as usize });
+        }
+    }
+
+    fn check_pool_index(&mut self, opcode: Opcode, index: PoolIndex, limit: u32) {
+        if index > limit {
+            self.report(EncodingError::PoolIndexOverflow { opcode, index: index as usize, limit: limit as usize });
         }
     }
 
-    // 14 bit on every operand
-    fn emit_binary_instr(&mut self, opcode: Opcode, first: u16, second: u16) {
-        // TODO check 14 bit
-        let payload = ((first as u32) << 14) as u32 | (second as u32);
+    // 14 bit on every operand, callers must check, that operands fit
+    fn emit_binary_instr(&mut self, opcode: Opcode, first: u32, second: u32) {
+        let payload = ((first & MAX_BINARY_OPERAND) << 14) | (second & MAX_BINARY_OPERAND);
         self.emit_instr(opcode, payload)
     }
 
-    pub fn get_pool_index(&mut self, value: u32) -> PoolIndex {
-        match self.cp_value_to_index.get(&value).map(|s| *s) {
+    /// Returns index of the value in constant pool, reports error if it exceeds `limit` of the `opcode` operand
+    fn get_pool_index(&mut self, opcode: Opcode, value: u32, limit: u32) -> PoolIndex {
+        let cp_index = match self.cp_value_to_index.get(&value) {
             None => {
                 let cp_index = self.cp_buffer.len() as PoolIndex;
                 self.cp_buffer.push(value);
                 self.cp_value_to_index.insert(value, cp_index);
-                // TODO check if there is enough place (only 14 bit)
                 cp_index
             },
-            Some(v) => v,
+            Some(v) => *v,
+        };
+        self.check_pool_index(opcode, cp_index, limit);
+        cp_index & limit
+    }
+
+    fn report(&mut self, error: EncodingError) {
+        if self.error.is_none() {
+            self.error = Some(error);
         }
     }
 
-    pub fn finish(&mut self) -> ProgramData {
+    /// Returns assembled program or the first encoding error, assembler is reset in both cases
+    pub fn finish(&mut self) -> Result<ProgramData, EncodingError> {
         let code = self.buffer.clone();
         let cp_buffer = self.cp_buffer.clone();
         self.buffer.clear();
         self.cp_buffer.clear();
-        ProgramData::new(code, cp_buffer)
+        self.cp_value_to_index.clear();
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(ProgramData::new(code, cp_buffer))
+        }
     }
 }
 
@@ -136,10 +350,101 @@ impl Assembler {
 pub struct ProgramData {
     pub code: Vec<u32>,
     pub constant_pool: Vec<u32>,
+    /// Ranks of token types by token type index, on equal length match token with greater rank wins
+    pub token_ranks: Vec<u32>,
+    /// Maps code to token definitions
+    pub debug_info: DebugInfo,
+    /// Mode stack changes by mode index and then by token type index
+    pub token_actions: Vec<Vec<TokenAction>>,
+    /// Program matches UTF-8 bytes instead of chars, see `Compiler::with_byte_mode`
+    pub byte_mode: bool,
 }
 
 impl ProgramData {
     pub fn new(code: Vec<u32>, constant_pool: Vec<u32>) -> Self {
-        ProgramData { code, constant_pool }
+        ProgramData { code, constant_pool, token_ranks: Vec::new(), debug_info: DebugInfo::default(), token_actions: Vec::new(), byte_mode: false }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disasm::decode;
+    use disasm::Instruction;
+
+    #[test]
+    fn split_widened_on_patch() {
+        let mut asm = Assembler::new();
+        let (then_marker, else_marker) = asm.emit_split(0, 0);
+        asm.patch_target(&then_marker, 1);
+        asm.patch_target(&else_marker, 1 << 14);
+        asm.patch_target(&then_marker, 2);
+        let program = asm.finish().unwrap();
+        assert_eq!(Instruction::SplitWide { table_index: 0 }, decode(program.code[0]));
+        assert_eq!(vec![2, 1 << 14], program.constant_pool);
+    }
+
+    #[test]
+    fn wide_split() {
+        let mut asm = Assembler::new();
+        asm.emit_char_cp('a');
+        asm.emit_split(1 << 20, 3);
+        let program = asm.finish().unwrap();
+        assert_eq!(Instruction::SplitWide { table_index: 1 }, decode(program.code[1]));
+        assert_eq!(vec!['a' as u32, 1 << 20, 3], program.constant_pool);
+    }
+
+    #[test]
+    fn wide_range() {
+        let mut asm = Assembler::new();
+        for code_point in 0..MAX_BINARY_OPERAND + 1 {
+            asm.emit_char_cp(std::char::from_u32(code_point).unwrap());
+        }
+        asm.emit_range_cp('\u{10000}', '\u{10001}');
+        let program = asm.finish().unwrap();
+        let pair_index = MAX_BINARY_OPERAND + 1;
+        assert_eq!(Instruction::RangeWide { table_index: pair_index }, decode(*program.code.last().unwrap()));
+        assert_eq!(&[0x10000, 0x10001], &program.constant_pool[pair_index as usize..]);
+    }
+
+    #[test]
+    fn wide_range_reserves_pair_only() {
+        let mut asm = Assembler::new();
+        for code_point in 0..MAX_BINARY_OPERAND {
+            asm.emit_char_cp(std::char::from_u32(code_point).unwrap());
+        }
+        // the first bound still fits, the second one doesn't
+        asm.emit_range_cp('\u{10000}', '\u{10001}');
+        asm.emit_range_cp('\u{0}', '\u{1}');
+        let program = asm.finish().unwrap();
+        let pair_index = MAX_BINARY_OPERAND;
+        assert_eq!(Instruction::RangeWide { table_index: pair_index }, decode(program.code[program.code.len() - 2]));
+        assert_eq!(Instruction::Range { from_index: 0, to_index: 1 }, decode(*program.code.last().unwrap()));
+        assert_eq!(pair_index as usize + 2, program.constant_pool.len());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn range_imm_overflow() {
+        let mut asm = Assembler::new();
+        asm.emit_range_imm('a', '\u{4000}');
+        assert_eq!(Err(EncodingError::OperandOverflow {
+            opcode: Opcode::RangeImm,
+            value: 0x4000,
+            limit: MAX_BINARY_OPERAND as usize
+        }), asm.finish().map(|_| ()));
+    }
+
+    #[test]
+    fn finish_resets_error() {
+        let mut asm = Assembler::new();
+        asm.emit_invert(1 << 16);
+        assert_eq!(Err(EncodingError::OperandOverflow {
+            opcode: Opcode::Invert,
+            value: 1 << 16,
+            limit: u16::MAX as usize
+        }), asm.finish().map(|_| ()));
+        asm.emit_char_cp('a');
+        let program = asm.finish().unwrap();
+        assert_eq!(vec!['a' as u32], program.constant_pool);
+    }
+}
@@ -1,33 +1,658 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
 
+/// Name of the mode, which consists of `LexerDefinition::tokens`, lexing starts in it
+pub const INITIAL_MODE_NAME: &str = "INITIAL";
 
 pub struct LexerDefinition {
-    pub tokens: Vec<TokenDefinition>
+    /// Tokens of the initial mode
+    pub tokens: Vec<TokenDefinition>,
+    /// Chooses token, when several of them match the same longest text, `TieBreak::HighestIndex` by default
+    pub tie_break: TieBreak,
+    /// Other modes, token of the mode is matched only while the mode is on top of the mode stack
+    pub modes: Vec<ModeDefinition>
 }
 
 impl LexerDefinition {
     pub fn new(tokens: Vec<TokenDefinition>) -> Self {
-        LexerDefinition { tokens }
+        LexerDefinition { tokens, tie_break: TieBreak::HighestIndex, modes: Vec::new() }
+    }
+
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: ModeDefinition) -> Self {
+        self.modes.push(mode);
+        self
+    }
+
+    /// Tokens of the initial mode followed by tokens of other modes
+    pub fn all_tokens(&self) -> impl Iterator<Item=&TokenDefinition> {
+        self.tokens.iter().chain(self.modes.iter().flat_map(|mode| mode.tokens.iter()))
+    }
+}
+
+/// Named set of tokens (start condition), e.g. contents of string literal with interpolation
+pub struct ModeDefinition {
+    pub name: String,
+    pub tokens: Vec<TokenDefinition>
+}
+
+impl ModeDefinition {
+    pub fn new(name: &str, tokens: Vec<TokenDefinition>) -> Self {
+        ModeDefinition { name: name.to_string(), tokens }
     }
 }
 
-#[derive(Clone)]
+/// Change of the mode stack after the token is matched, modes are referred by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeAction {
+    None,
+    /// Enters the mode, previous one is restored by `Pop`
+    Push(String),
+    /// Returns to the previous mode, does nothing in the mode at the bottom of the stack
+    Pop,
+    /// Replaces the current mode
+    Switch(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TieBreak {
+    /// Token with greater `TokenDefinition::index` wins, default of `LexerDefinition::new`
+    HighestIndex,
+    /// Token, which is defined earlier in `LexerDefinition::tokens`, wins
+    FirstDefined,
+    /// Token with greater `TokenDefinition::priority` wins, equal priorities are resolved as in `FirstDefined`
+    Priority,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Expr {
     Single { ch: char },
     Range { from: char, to: char },
     Or { variants: Vec<Expr> },
     Seq { exprs: Vec<Expr> },
-    Loop { expr: Box<Expr> }
+    Loop { expr: Box<Expr> },
+    /// Counted repetition `expr{min,max}`, `max` is `None` when it is unbounded
+    Repeat { expr: Box<Expr>, min: u32, max: Option<u32> },
+    /// Union of inclusive char ranges, matches any char outside of them if `negated`
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    /// Any char, `\n` is matched only if `matches_newline` is set
+    Any { matches_newline: bool }
 }
 
 pub struct TokenDefinition {
     pub expr: Expr,
     pub index: u16,
-    pub name: String
+    pub name: String,
+    /// Used with `TieBreak::Priority`, e.g. keywords should have greater priority than identifiers
+    pub priority: i32,
+    pub action: ModeAction
+}
+
+impl TokenDefinition {
+    pub fn new(index: u16, name: &str, expr: Expr) -> Self {
+        TokenDefinition { expr, index, name: name.to_string(), priority: 0, action: ModeAction::None }
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_action(mut self, action: ModeAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Creates definition with expression, parsed from regex `pattern`
+    pub fn from_pattern(index: u16, name: &str, pattern: &str) -> Result<Self, ParseError> {
+        Ok(TokenDefinition::new(index, name, parse(pattern)?))
+    }
+}
+
+/// Byte range in the parsed pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// `(` without matching `)`
+    UnclosedParen,
+    /// `)` without matching `(`
+    UnopenedParen,
+    /// Quantifier with nothing to repeat, e.g. `*a` or `(+)`
+    DanglingQuantifier,
+    /// Unknown or malformed escape sequence
+    BadEscape,
+    /// Escape denotes value, which is not a valid char
+    InvalidCodePoint,
+    /// `[]`
+    EmptyClass,
+    /// `[` without matching `]`
+    UnclosedClass,
+    /// Range with bounds in wrong order, e.g. `[z-a]`
+    ReversedRange { from: char, to: char },
+    /// Malformed counted repetition, e.g. `a{x}` or `a{1`
+    BadRepetition,
+    /// Counted repetition with bounds in wrong order, e.g. `a{3,2}`
+    ReversedRepetition { min: u32, max: u32 },
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnclosedParen => write!(f, "unclosed group, expected `)`"),
+            ParseErrorKind::UnopenedParen => write!(f, "unexpected `)` without matching `(`"),
+            ParseErrorKind::DanglingQuantifier => write!(f, "quantifier has nothing to repeat"),
+            ParseErrorKind::BadEscape => write!(f, "bad escape sequence"),
+            ParseErrorKind::InvalidCodePoint => write!(f, "escape does not denote a valid char"),
+            ParseErrorKind::EmptyClass => write!(f, "empty character class"),
+            ParseErrorKind::UnclosedClass => write!(f, "unclosed character class, expected `]`"),
+            ParseErrorKind::ReversedRange { from, to } =>
+                write!(f, "range start {:?} is greater than range end {:?}", from, to),
+            ParseErrorKind::BadRepetition => write!(f, "malformed repetition, expected `{{n}}`, `{{n,}}` or `{{n,m}}`"),
+            ParseErrorKind::ReversedRepetition { min, max } =>
+                write!(f, "repetition minimum {} is greater than maximum {}", min, max),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> Self {
+        ParseError { kind, span }
+    }
+
+    /// Renders message together with the pattern and caret underline below the erroneous part:
+    /// ```text
+    /// error: unclosed group, expected `)`
+    ///   a(bc
+    ///    ^
+    /// ```
+    pub fn render(&self, pattern: &str) -> String {
+        let start = self.span.start.min(pattern.len());
+        let end = self.span.end.min(pattern.len()).max(start);
+        let offset = pattern[..start].chars().count();
+        let width = pattern[start..end].chars().count().max(1);
+        format!("error: {}\n  {}\n  {}{}", self.kind, pattern, " ".repeat(offset), "^".repeat(width))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} at {}..{}", self.kind, self.span.start, self.span.end)
+    }
 }
 
-// TODO make enum
-pub struct ParseError;
+impl Error for ParseError {}
 
+/// Parses regex pattern into expression tree.
+///
+/// Supported syntax: literals, `|`, grouping with `()`, `*`, `+`, `?`, `{n}`, `{n,}`, `{n,m}`, classes like `[a-z_]`
+/// or `[^"\\]`, `.` (any char except newline) and escapes (`\n`, `\t`, `\r`, `\d`, `\w`, `\s`, `\D`, `\W`, `\S`, `\xHH`,
+/// `\u{HHHH}` and escaped metacharacters).
 pub fn parse(text: &str) -> Result<Expr, ParseError> {
-    unimplemented!()
-}
\ No newline at end of file
+    let mut parser = Parser::new(text);
+    let expr = parser.parse_alternation()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(_) => Err(parser.error_at_current(ParseErrorKind::UnopenedParen))
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Parser { text, position: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.position += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Error, spanning from `start` to the current position
+    fn error(&self, kind: ParseErrorKind, start: usize) -> ParseError {
+        ParseError::new(kind, Span::new(start, self.position))
+    }
+
+    /// Error, pointing to the char at the current position
+    fn error_at_current(&self, kind: ParseErrorKind) -> ParseError {
+        let len = self.peek().map(|ch| ch.len_utf8()).unwrap_or(0);
+        ParseError::new(kind, Span::new(self.position, self.position + len))
+    }
+
+    /// alternation := concat ('|' concat)*
+    fn parse_alternation(&mut self) -> Result<Expr, ParseError> {
+        let mut variants = vec![self.parse_concat()?];
+        while self.eat('|') {
+            variants.push(self.parse_concat()?);
+        }
+        if variants.len() == 1 {
+            Ok(variants.pop().unwrap())
+        } else {
+            Ok(Expr::Or { variants })
+        }
+    }
+
+    /// concat := repeat*
+    fn parse_concat(&mut self) -> Result<Expr, ParseError> {
+        let mut exprs = Vec::new();
+        while let Some(ch) = self.peek() {
+            if ch == '|' || ch == ')' {
+                break;
+            }
+            exprs.push(self.parse_repeat()?);
+        }
+        if exprs.len() == 1 {
+            Ok(exprs.pop().unwrap())
+        } else {
+            Ok(Expr::Seq { exprs })
+        }
+    }
+
+    /// repeat := atom ('*' | '+' | '?' | '{' counts '}')*
+    fn parse_repeat(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_atom()?;
+        loop {
+            expr = match self.peek() {
+                Some('*') => {
+                    self.bump();
+                    Expr::Loop { expr: Box::new(expr) }
+                }
+                Some('+') => {
+                    self.bump();
+                    Expr::Seq { exprs: vec![expr.clone(), Expr::Loop { expr: Box::new(expr) }] }
+                }
+                Some('?') => {
+                    self.bump();
+                    Expr::Or { variants: vec![expr, Expr::Seq { exprs: vec![] }] }
+                }
+                Some('{') => {
+                    let (min, max) = self.parse_counts()?;
+                    Expr::Repeat { expr: Box::new(expr), min, max }
+                }
+                _ => return Ok(expr)
+            };
+        }
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}` into minimal and maximal count
+    fn parse_counts(&mut self) -> Result<(u32, Option<u32>), ParseError> {
+        let start = self.position;
+        self.bump();
+        let min = self.parse_number().ok_or_else(|| self.error(ParseErrorKind::BadRepetition, start))?;
+        let max = if self.eat(',') {
+            if self.peek() == Some('}') {
+                None
+            } else {
+                Some(self.parse_number().ok_or_else(|| self.error(ParseErrorKind::BadRepetition, start))?)
+            }
+        } else {
+            Some(min)
+        };
+        if !self.eat('}') {
+            return Err(self.error(ParseErrorKind::BadRepetition, start));
+        }
+        match max {
+            Some(max) if max < min => Err(self.error(ParseErrorKind::ReversedRepetition { min, max }, start)),
+            _ => Ok((min, max))
+        }
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let start = self.position;
+        while let Some(ch) = self.peek() {
+            if !ch.is_ascii_digit() {
+                break;
+            }
+            self.bump();
+        }
+        self.text[start..self.position].parse().ok()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, ParseError> {
+        let start = self.position;
+        match self.bump() {
+            Some('(') => {
+                let expr = self.parse_alternation()?;
+                if !self.eat(')') {
+                    return Err(ParseError::new(ParseErrorKind::UnclosedParen, Span::new(start, start + 1)));
+                }
+                Ok(expr)
+            }
+            Some('[') => self.parse_class(start),
+            Some('.') => Ok(Expr::Any { matches_newline: false }),
+            Some('\\') => self.parse_escape(start),
+            Some('*') | Some('+') | Some('?') | Some('{') => Err(self.error(ParseErrorKind::DanglingQuantifier, start)),
+            Some(ch) => Ok(Expr::Single { ch }),
+            None => unreachable!("atom is parsed only in front of a char")
+        }
+    }
+
+    /// Parses escape sequence after `\`, which is located at `start`
+    fn parse_escape(&mut self, start: usize) -> Result<Expr, ParseError> {
+        if let Some((ranges, negated)) = self.parse_shorthand() {
+            return Ok(class_expr(ranges.to_vec(), negated));
+        }
+        Ok(Expr::Single { ch: self.parse_escaped_char(start)? })
+    }
+
+    /// Parses shorthand class (`d`, `w`, `s` or their negated uppercase forms) after `\`
+    fn parse_shorthand(&mut self) -> Option<(&'static [(char, char)], bool)> {
+        let ch = self.peek()?;
+        let ranges = match ch.to_ascii_lowercase() {
+            'd' => DIGIT_RANGES,
+            'w' => WORD_RANGES,
+            's' => SPACE_RANGES,
+            _ => return None
+        };
+        self.bump();
+        Some((ranges, ch.is_ascii_uppercase()))
+    }
+
+    /// Parses escape sequence, denoting single char (after `\`, which is located at `start`)
+    fn parse_escaped_char(&mut self, start: usize) -> Result<char, ParseError> {
+        match self.bump() {
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('t') => Ok('\t'),
+            Some('0') => Ok('\0'),
+            Some('x') => {
+                let digits_start = self.position;
+                for _ in 0..2 {
+                    match self.peek() {
+                        Some(ch) if ch.is_ascii_hexdigit() => { self.bump(); }
+                        _ => return Err(self.error(ParseErrorKind::BadEscape, start))
+                    }
+                }
+                self.code_point(start, digits_start, self.position)
+            }
+            Some('u') => {
+                if !self.eat('{') {
+                    return Err(self.error(ParseErrorKind::BadEscape, start));
+                }
+                let digits_start = self.position;
+                while let Some(ch) = self.peek() {
+                    if !ch.is_ascii_hexdigit() {
+                        break;
+                    }
+                    self.bump();
+                }
+                let digits_end = self.position;
+                if !self.eat('}') || digits_end == digits_start {
+                    return Err(self.error(ParseErrorKind::BadEscape, start));
+                }
+                self.code_point(start, digits_start, digits_end)
+            }
+            Some(ch) if is_meta_char(ch) => Ok(ch),
+            _ => Err(self.error(ParseErrorKind::BadEscape, start))
+        }
+    }
+
+    fn code_point(&self, start: usize, digits_start: usize, digits_end: usize) -> Result<char, ParseError> {
+        u32::from_str_radix(&self.text[digits_start..digits_end], 16).ok()
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| self.error(ParseErrorKind::InvalidCodePoint, start))
+    }
+
+    /// Parses class after `[`, which is located at `start`
+    fn parse_class(&mut self, start: usize) -> Result<Expr, ParseError> {
+        let unclosed = ParseError::new(ParseErrorKind::UnclosedClass, Span::new(start, start + 1));
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        loop {
+            match self.peek() {
+                Some(']') => {
+                    self.bump();
+                    if ranges.is_empty() {
+                        return Err(self.error(ParseErrorKind::EmptyClass, start));
+                    }
+                    break;
+                }
+                None => return Err(unclosed),
+                _ => {}
+            }
+            let item_start = self.position;
+            if self.eat('\\') {
+                if let Some((shorthand, shorthand_negated)) = self.parse_shorthand() {
+                    if shorthand_negated {
+                        ranges.extend(complement(shorthand));
+                    } else {
+                        ranges.extend_from_slice(shorthand);
+                    }
+                    continue;
+                }
+                self.position = item_start;
+            }
+            let from = self.parse_class_char()?;
+            if self.peek() == Some('-') && !self.text[self.position + 1..].starts_with(']') {
+                self.bump();
+                if self.peek().is_none() {
+                    return Err(unclosed);
+                }
+                let to = self.parse_class_char()?;
+                if to < from {
+                    return Err(self.error(ParseErrorKind::ReversedRange { from, to }, item_start));
+                }
+                ranges.push((from, to));
+            } else {
+                ranges.push((from, from));
+            }
+        }
+        Ok(class_expr(ranges, negated))
+    }
+
+    fn parse_class_char(&mut self) -> Result<char, ParseError> {
+        let start = self.position;
+        match self.bump() {
+            Some('\\') => self.parse_escaped_char(start),
+            Some(ch) => Ok(ch),
+            None => unreachable!("class char is parsed only in front of a char")
+        }
+    }
+}
+
+fn is_meta_char(ch: char) -> bool {
+    "\\.*+?|()[]{}^$-/\"'".contains(ch)
+}
+
+const DIGIT_RANGES: &[(char, char)] = &[('0', '9')];
+const WORD_RANGES: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE_RANGES: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+/// Simple non negated classes of single range are represented as `Single` or `Range`
+fn class_expr(ranges: Vec<(char, char)>, negated: bool) -> Expr {
+    if !negated && ranges.len() == 1 {
+        let (from, to) = ranges[0];
+        if from == to {
+            return Expr::Single { ch: from };
+        }
+        return Expr::Range { from, to };
+    }
+    Expr::Class { ranges, negated }
+}
+
+/// Computes sorted disjoint ranges of all chars, that are not covered by `ranges`
+pub fn complement(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut sorted: Vec<(u32, u32)> = ranges.iter()
+        .map(|&(from, to)| (from as u32, to as u32))
+        .collect();
+    sorted.sort();
+    let mut result = Vec::new();
+    let mut next = 0u32;
+    for (from, to) in sorted {
+        if from > next {
+            push_char_range(&mut result, next, from - 1);
+        }
+        next = next.max(to + 1);
+    }
+    if next <= std::char::MAX as u32 {
+        push_char_range(&mut result, next, std::char::MAX as u32);
+    }
+    result
+}
+
+/// Pushes range of code points, excluding surrogates, which are not chars
+fn push_char_range(result: &mut Vec<(char, char)>, from: u32, to: u32) {
+    const SURROGATES_START: u32 = 0xD800;
+    const SURROGATES_END: u32 = 0xDFFF;
+    let pieces = [
+        (from, to.min(SURROGATES_START - 1)),
+        (from.max(SURROGATES_END + 1), to)
+    ];
+    for &(from, to) in pieces.iter() {
+        if from <= to {
+            result.push((std::char::from_u32(from).unwrap(), std::char::from_u32(to).unwrap()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::Expr::*;
+
+    fn single(ch: char) -> Expr {
+        Single { ch }
+    }
+
+    fn error(kind: ParseErrorKind, start: usize, end: usize) -> Result<Expr, ParseError> {
+        Err(ParseError::new(kind, Span::new(start, end)))
+    }
+
+    #[test]
+    fn literal() {
+        assert_eq!(Ok(single('a')), parse("a"));
+        assert_eq!(Ok(Seq { exprs: vec![single('a'), single('b')] }), parse("ab"));
+    }
+
+    #[test]
+    fn alternation() {
+        assert_eq!(Ok(Or { variants: vec![
+            Seq { exprs: vec![single('a'), single('b')] },
+            single('c')
+        ] }), parse("ab|c"));
+    }
+
+    #[test]
+    fn group() {
+        assert_eq!(Ok(Seq { exprs: vec![
+            single('a'),
+            Or { variants: vec![single('b'), single('c')] }
+        ] }), parse("a(b|c)"));
+    }
+
+    #[test]
+    fn quantifiers() {
+        assert_eq!(Ok(Loop { expr: Box::new(single('a')) }), parse("a*"));
+        assert_eq!(Ok(Seq { exprs: vec![single('a'), Loop { expr: Box::new(single('a')) }] }), parse("a+"));
+        assert_eq!(Ok(Or { variants: vec![single('a'), Seq { exprs: vec![] }] }), parse("a?"));
+    }
+
+    #[test]
+    fn counted_repetition() {
+        let a = Box::new(single('a'));
+        assert_eq!(Ok(Repeat { expr: a.clone(), min: 2, max: Some(2) }), parse("a{2}"));
+        assert_eq!(Ok(Repeat { expr: a.clone(), min: 2, max: None }), parse("a{2,}"));
+        assert_eq!(Ok(Repeat { expr: a.clone(), min: 2, max: Some(4) }), parse("a{2,4}"));
+        assert_eq!(Ok(Repeat { expr: Box::new(Range { from: '0', to: '9' }), min: 4, max: Some(4) }), parse(r"\d{4}"));
+    }
+
+    #[test]
+    fn class() {
+        assert_eq!(Ok(Range { from: 'a', to: 'z' }), parse("[a-z]"));
+        assert_eq!(Ok(Class { ranges: vec![('a', 'z'), ('_', '_'), ('-', '-')], negated: false }), parse("[a-z_-]"));
+        assert_eq!(Ok(Class { ranges: vec![(']', ']'), ('\n', '\n')], negated: false }), parse(r"[\]\n]"));
+        assert_eq!(Ok(Class { ranges: vec![('0', '9'), ('.', '.')], negated: false }), parse(r"[\d.]"));
+    }
+
+    #[test]
+    fn negated_class() {
+        assert_eq!(Ok(Class { ranges: vec![('"', '"'), ('\\', '\\'), ('\n', '\n')], negated: true }), parse(r#"[^"\\\n]"#));
+        assert_eq!(Ok(Class { ranges: vec![('0', '9')], negated: true }), parse(r"\D"));
+        assert_eq!(Ok(Class { ranges: vec![('\0', '/'), (':', '\u{D7FF}'), ('\u{E000}', std::char::MAX)], negated: false }),
+                   parse(r"[\D]"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(Ok(single('*')), parse(r"\*"));
+        assert_eq!(Ok(single('\t')), parse(r"\t"));
+        assert_eq!(Ok(single('A')), parse(r"\x41"));
+        assert_eq!(Ok(single('\u{1F600}')), parse(r"\u{1F600}"));
+        assert_eq!(Ok(Range { from: '0', to: '9' }), parse(r"\d"));
+    }
+
+    #[test]
+    fn dot() {
+        assert_eq!(Ok(Any { matches_newline: false }), parse("."));
+        assert_eq!(Ok(Seq { exprs: vec![single('/'), single('/'), Loop { expr: Box::new(Any { matches_newline: false }) }] }),
+                   parse("//.*"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error(ParseErrorKind::UnclosedParen, 1, 2), parse("a(b"));
+        assert_eq!(error(ParseErrorKind::UnopenedParen, 1, 2), parse("a)"));
+        assert_eq!(error(ParseErrorKind::DanglingQuantifier, 0, 1), parse("*a"));
+        assert_eq!(error(ParseErrorKind::DanglingQuantifier, 3, 4), parse("a|(+)"));
+        assert_eq!(error(ParseErrorKind::EmptyClass, 0, 2), parse("[]"));
+        assert_eq!(error(ParseErrorKind::EmptyClass, 0, 3), parse("[^]"));
+        assert_eq!(error(ParseErrorKind::UnclosedClass, 1, 2), parse("a[bc"));
+        assert_eq!(error(ParseErrorKind::ReversedRange { from: 'z', to: 'a' }, 2, 5), parse("[_z-a]"));
+        assert_eq!(error(ParseErrorKind::BadEscape, 1, 3), parse(r"a\q"));
+        assert_eq!(error(ParseErrorKind::BadEscape, 0, 2), parse(r"\xZZ"));
+        assert_eq!(error(ParseErrorKind::InvalidCodePoint, 0, 8), parse(r"\u{D800}"));
+        assert_eq!(error(ParseErrorKind::BadRepetition, 1, 2), parse("a{x}"));
+        assert_eq!(error(ParseErrorKind::BadRepetition, 1, 3), parse("a{1"));
+        assert_eq!(error(ParseErrorKind::ReversedRepetition { min: 3, max: 2 }, 1, 6), parse("a{3,2}"));
+        assert_eq!(error(ParseErrorKind::DanglingQuantifier, 0, 1), parse("{2}"));
+    }
+
+    #[test]
+    fn render() {
+        let pattern = "ab[z-a]";
+        let error = parse(pattern).unwrap_err();
+        assert_eq!("error: range start 'z' is greater than range end 'a'\n  ab[z-a]\n     ^^^", error.render(pattern));
+        let pattern = "\u{3b1}(\u{3b2}";
+        let error = parse(pattern).unwrap_err();
+        assert_eq!("error: unclosed group, expected `)`\n  \u{3b1}(\u{3b2}\n   ^", error.render(pattern));
+    }
+}
@@ -0,0 +1,159 @@
+//! Interactive step debugger for lexers.
+//!
+//! Usage: `lexer-debug <definition file> <input file>`, where every non empty line of the definition
+//! file is `NAME pattern`, lines starting with `#` are ignored.
+
+extern crate lexer_vm;
+
+use lexer_vm::{Lexer, LexerDefinition, TokenDefinition, Debugger, DebugEvent, Breakpoint, StopReason};
+use lexer_vm::disasm::decode;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::process;
+
+const HELP: &str = "\
+s, step [n]          feed next char or finish token, n times
+c, continue          run until breakpoint or end of text
+b, break pc|offset N set breakpoint on code pointer or input offset
+d, delete pc|offset N
+l, breakpoints       list breakpoints
+t, threads           show active threads
+tokens               show produced tokens
+code                 show program
+h, help
+q, quit";
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <definition file> <input file>", args[0]);
+        process::exit(2);
+    }
+    let definition = read_definition(&args[1]).unwrap_or_else(|message| fail(&message));
+    let text = fs::read_to_string(&args[2]).unwrap_or_else(|error| fail(&format!("{}: {}", args[2], error)));
+    let lexer = Lexer::new(&definition).unwrap_or_else(|error| fail(&error.to_string()));
+    let mut debugger = lexer.vm().debug(&text);
+    let stdin = io::stdin();
+    prompt();
+    for line in stdin.lock().lines() {
+        let line = line.unwrap_or_else(|error| fail(&error.to_string()));
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first().cloned() {
+            None => {}
+            Some("s") | Some("step") => {
+                let count = words.get(1).and_then(|count| count.parse().ok()).unwrap_or(1);
+                for _ in 0..count {
+                    let event = debugger.step();
+                    print_event(&lexer, event);
+                    if event == DebugEvent::Finished {
+                        break;
+                    }
+                }
+            }
+            Some("c") | Some("continue") => match debugger.run() {
+                StopReason::Breakpoint(breakpoint) => println!("stopped at {:?}, offset {}", breakpoint, debugger.offset()),
+                StopReason::Finished => println!("finished, {} tokens", debugger.tokens().len()),
+            },
+            Some("b") | Some("break") => match parse_breakpoint(&words[1..]) {
+                Some(breakpoint) => debugger.add_breakpoint(breakpoint),
+                None => println!("expected: break pc|offset N"),
+            },
+            Some("d") | Some("delete") => match parse_breakpoint(&words[1..]) {
+                Some(breakpoint) => if !debugger.remove_breakpoint(breakpoint) {
+                    println!("no such breakpoint")
+                },
+                None => println!("expected: delete pc|offset N"),
+            },
+            Some("l") | Some("breakpoints") => {
+                for breakpoint in debugger.breakpoints() {
+                    println!("{:?}", breakpoint);
+                }
+            }
+            Some("t") | Some("threads") => print_threads(&debugger),
+            Some("tokens") => {
+                let mut start = 0;
+                for token in debugger.tokens() {
+                    let end = start + token.length() as usize;
+                    println!("{} {:?} {}..{}", token_name(&lexer, token.token_type_index()), &text[start..end], start, end);
+                    start = end;
+                }
+            }
+            Some("code") => {
+                for (code_pointer, code) in lexer.vm().code().iter().enumerate() {
+                    let token = lexer.vm().debug_info().token_at(code_pointer as u32);
+                    let name = token.map(|token| token.name.as_str()).unwrap_or("");
+                    println!("{:4} {:40} {}", code_pointer, decode(*code).to_string(), name);
+                }
+            }
+            Some("h") | Some("help") => println!("{}", HELP),
+            Some("q") | Some("quit") => break,
+            Some(command) => println!("unknown command {}, type help", command),
+        }
+        prompt();
+    }
+}
+
+fn read_definition(path: &str) -> Result<LexerDefinition, String> {
+    let source = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    let mut tokens = Vec::new();
+    for line in source.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let name = parts.next().unwrap();
+        let pattern = parts.next().map(|pattern| pattern.trim()).unwrap_or("");
+        let index = tokens.len() as u16 + 2;
+        let token = TokenDefinition::from_pattern(index, name, pattern)
+            .map_err(|error| format!("token {}:\n{}", name, error.render(pattern)))?;
+        tokens.push(token);
+    }
+    Ok(LexerDefinition::new(tokens))
+}
+
+fn parse_breakpoint(words: &[&str]) -> Option<Breakpoint> {
+    let value = words.get(1)?.parse().ok()?;
+    match words[0] {
+        "pc" => Some(Breakpoint::CodePointer(value as u32)),
+        "offset" => Some(Breakpoint::Offset(value)),
+        _ => None,
+    }
+}
+
+fn print_event(lexer: &Lexer, event: DebugEvent) {
+    match event {
+        DebugEvent::MatchStart { offset } => println!("match start at {}", offset),
+        DebugEvent::Char { offset, ch } => println!("char {:?} at {}", ch, offset),
+        DebugEvent::Token(token) => println!("token {} of length {}", token_name(lexer, token.token_type_index()), token.length()),
+        DebugEvent::Finished => println!("finished"),
+    }
+}
+
+fn print_threads(debugger: &Debugger) {
+    for thread in debugger.threads() {
+        let name = thread.token.map(|token| token.name.as_str()).unwrap_or("");
+        println!("{:4} {:40} {}", thread.code_pointer, thread.instruction.to_string(), name);
+    }
+    if let Some((token_type_index, end)) = debugger.best_match() {
+        println!("best match: token type {} up to {}", token_type_index, end);
+    }
+}
+
+fn token_name(lexer: &Lexer, token_type_index: u16) -> String {
+    lexer.token_name(token_type_index)
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| token_type_index.to_string())
+}
+
+fn prompt() {
+    print!("> ");
+    let _ = io::stdout().flush();
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
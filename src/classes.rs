@@ -0,0 +1,207 @@
+use disasm::decode;
+use disasm::Instruction;
+use std::char::from_u32;
+
+const MAX_CODE_POINT: u32 = 0x10FFFF;
+const SURROGATES_START: u32 = 0xD800;
+const SURROGATES_END: u32 = 0xDFFF;
+const ASCII_LEN: usize = 128;
+
+/// Partition of code points into intervals, which no matching instruction of a program can distinguish
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharClasses {
+    /// Sorted first code points of the classes, the first one is always 0
+    starts: Vec<u32>,
+    /// Class by ASCII char, there are at most 128 ASCII classes, so they fit into u8
+    ascii: [u8; ASCII_LEN],
+}
+
+/// Set of classes, which is matched by a single instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassSet {
+    /// Instruction doesn't match chars by itself
+    Empty,
+    /// Inclusive range of classes
+    Range(usize, usize),
+    /// All classes except the one
+    AllExcept(usize),
+}
+
+impl ClassSet {
+    pub fn contains(&self, class: usize) -> bool {
+        match *self {
+            ClassSet::Empty => false,
+            ClassSet::Range(from, to) => from <= class && class <= to,
+            ClassSet::AllExcept(excluded) => class != excluded,
+        }
+    }
+}
+
+impl CharClasses {
+    /// Builds classes, splitting code points at every boundary, boundary is the first code point of a class
+    pub fn new(mut boundaries: Vec<u32>) -> Self {
+        boundaries.push(0);
+        boundaries.retain(|&boundary| boundary <= MAX_CODE_POINT);
+        boundaries.sort();
+        boundaries.dedup();
+        let mut ascii = [0; ASCII_LEN];
+        let mut class = 0;
+        for (code_point, ascii_class) in ascii.iter_mut().enumerate() {
+            while class + 1 < boundaries.len() && boundaries[class + 1] <= code_point as u32 {
+                class += 1;
+            }
+            *ascii_class = class as u8;
+        }
+        CharClasses { starts: boundaries, ascii }
+    }
+
+    /// Classes of all chars, matched by instructions of the program
+    pub fn from_program(code: &[u32], constant_pool: &[u32]) -> Self {
+        let mut boundaries = Vec::new();
+        for &instruction in code {
+            let instruction = decode(instruction);
+            let range = match instruction {
+                Instruction::Any { matches_newline: false } => Some(('\n' as u32, '\n' as u32)),
+                _ => matched_range(&instruction, constant_pool)
+            };
+            if let Some((from, to)) = range {
+                boundaries.push(from);
+                boundaries.push(to + 1);
+            }
+        }
+        CharClasses::new(boundaries)
+    }
+
+    /// Classes, matched by the instruction, classes must be built from the program of the instruction
+    pub fn class_set(&self, instruction: &Instruction, constant_pool: &[u32]) -> ClassSet {
+        match *instruction {
+            Instruction::Any { matches_newline: true } => ClassSet::Range(0, self.len() - 1),
+            Instruction::Any { matches_newline: false } => ClassSet::AllExcept(self.class_of('\n')),
+            _ => match matched_range(instruction, constant_pool) {
+                Some((from, to)) => ClassSet::Range(self.class_of_code_point(from), self.class_of_code_point(to)),
+                None => ClassSet::Empty,
+            }
+        }
+    }
+
+    /// First code points of the classes
+    pub fn starts(&self) -> &[u32] {
+        &self.starts
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    #[inline]
+    pub fn class_of(&self, ch: char) -> usize {
+        let code_point = ch as u32;
+        if code_point < ASCII_LEN as u32 {
+            self.ascii[code_point as usize] as usize
+        } else {
+            self.class_of_code_point(code_point)
+        }
+    }
+
+    pub fn class_of_code_point(&self, code_point: u32) -> usize {
+        match self.starts.binary_search(&code_point) {
+            Ok(class) => class,
+            Err(next_class) => next_class - 1,
+        }
+    }
+
+    /// Inclusive code point bounds of the class
+    pub fn bounds(&self, class: usize) -> (u32, u32) {
+        let end = self.starts.get(class + 1).map(|next| next - 1).unwrap_or(MAX_CODE_POINT);
+        (self.starts[class], end)
+    }
+
+    /// Some char of the class, None if the class consists of surrogates only
+    pub fn representative(&self, class: usize) -> Option<char> {
+        let (from, to) = self.bounds(class);
+        if (SURROGATES_START..=SURROGATES_END).contains(&from) {
+            if to > SURROGATES_END { from_u32(SURROGATES_END + 1) } else { None }
+        } else {
+            from_u32(from)
+        }
+    }
+}
+
+/// Inclusive code point range, matched by the char or range instruction
+fn matched_range(instruction: &Instruction, constant_pool: &[u32]) -> Option<(u32, u32)> {
+    match *instruction {
+        Instruction::CharImm { ch } => Some((ch as u32, ch as u32)),
+        Instruction::CharCp { ch_index } => {
+            let code_point = constant_pool[ch_index as usize];
+            Some((code_point, code_point))
+        }
+        Instruction::RangeImm { from, to } => Some((from as u32, to as u32)),
+        Instruction::Range { from_index, to_index } =>
+            Some((constant_pool[from_index as usize], constant_pool[to_index as usize])),
+        Instruction::RangeWide { table_index } =>
+            Some((constant_pool[table_index as usize], constant_pool[table_index as usize + 1])),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    #[test]
+    fn from_program() {
+        let mut asm = Assembler::new();
+        asm.emit_range_imm('a', 'z');
+        asm.emit_char_imm('x');
+        asm.emit_any(false);
+        let program = asm.finish().unwrap();
+        let classes = CharClasses::from_program(&program.code, &program.constant_pool);
+        assert_eq!(vec![0, '\n' as u32, '\n' as u32 + 1, 'a' as u32, 'x' as u32, 'y' as u32, 'z' as u32 + 1], classes.starts);
+        assert_eq!(classes.class_of('b'), classes.class_of('w'));
+        assert_ne!(classes.class_of('w'), classes.class_of('x'));
+        assert_eq!(classes.class_of('A'), classes.class_of('\r'));
+        assert_eq!(('{' as u32, MAX_CODE_POINT), classes.bounds(classes.len() - 1));
+    }
+
+    #[test]
+    fn ascii_table() {
+        let classes = CharClasses::new(vec!['0' as u32, '9' as u32 + 1, 'a' as u32, 0x3b1]);
+        for code_point in 0..0x400 {
+            let ch = from_u32(code_point).unwrap();
+            assert_eq!(classes.class_of_code_point(code_point), classes.class_of(ch), "char: {:?}", ch);
+        }
+    }
+
+    #[test]
+    fn class_set() {
+        let mut asm = Assembler::new();
+        asm.emit_range_imm('a', 'z');
+        asm.emit_any(false);
+        asm.emit_char('\u{1F600}');
+        let program = asm.finish().unwrap();
+        let classes = CharClasses::from_program(&program.code, &program.constant_pool);
+        let sets: Vec<ClassSet> = program.code.iter()
+            .map(|&instruction| classes.class_set(&decode(instruction), &program.constant_pool))
+            .collect();
+        assert!(sets[0].contains(classes.class_of('q')));
+        assert!(!sets[0].contains(classes.class_of('{')));
+        assert!(sets[1].contains(classes.class_of('\u{1F600}')));
+        assert!(!sets[1].contains(classes.class_of('\n')));
+        assert!(sets[2].contains(classes.class_of('\u{1F600}')));
+        assert!(!sets[2].contains(classes.class_of('\u{1F601}')));
+    }
+
+    #[test]
+    fn surrogates() {
+        let classes = CharClasses::new(vec![SURROGATES_START, SURROGATES_END + 1, SURROGATES_END + 5]);
+        assert_eq!(None, classes.representative(1));
+        assert_eq!(from_u32(SURROGATES_END + 1), classes.representative(2));
+        let classes = CharClasses::new(vec![SURROGATES_START]);
+        assert_eq!(from_u32(SURROGATES_END + 1), classes.representative(1));
+    }
+}
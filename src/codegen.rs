@@ -0,0 +1,463 @@
+use ast::LexerDefinition;
+use classes::CharClasses;
+use compiler::CompileError;
+use compiler::Compiler;
+use dfa::Dfa;
+use dfa::DfaError;
+use dfa::DEAD_STATE;
+use dfa::START_STATE;
+use lexer::ERROR_TOKEN_NAME;
+use lexer::END_TOKEN_NAME;
+use table::DfaTable;
+use vm::ERROR_TOKEN_INDEX;
+use vm::END_TOKEN_INDEX;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::fmt::Write;
+
+/// Name of the token type enum in the generated lexer
+const TOKEN_ENUM_NAME: &str = "TokenType";
+/// Number of array elements per line of generated code
+const ELEMENTS_PER_LINE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodegenError {
+    Compile(CompileError),
+    Dfa(DfaError),
+    /// Generated lexer has no mode stack, so definition must have the initial mode only
+    ModesNotSupported,
+}
+
+impl From<CompileError> for CodegenError {
+    fn from(error: CompileError) -> Self {
+        CodegenError::Compile(error)
+    }
+}
+
+impl From<DfaError> for CodegenError {
+    fn from(error: DfaError) -> Self {
+        CodegenError::Dfa(error)
+    }
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CodegenError::Compile(error) => write!(f, "{}", error),
+            CodegenError::Dfa(error) => write!(f, "{}", error),
+            CodegenError::ModesNotSupported => write!(f, "lexer modes are not supported by generated code"),
+        }
+    }
+}
+
+impl Error for CodegenError {}
+
+/// Generates Rust module with the lexer of the definition, which doesn't depend on this crate.
+/// Module contains `TokenType` enum, `Token` struct and `tokenize` function, e.g. for `build.rs`:
+/// ```ignore
+/// let code = lexer_vm::codegen::generate_rust(&definition)?;
+/// fs::write(Path::new(&env::var("OUT_DIR")?).join("lexer.rs"), code)?;
+/// ```
+pub fn generate_rust(definition: &LexerDefinition) -> Result<String, CodegenError> {
+    if !definition.modes.is_empty() {
+        return Err(CodegenError::ModesNotSupported);
+    }
+    let mut compiler = Compiler::new();
+    compiler.generate_lexer(definition)?;
+    let program = compiler.get_prog_data()?;
+    let table = Dfa::from_program(&program)?.minimize().to_table();
+    let variants = variant_names(definition);
+    let mut code = String::new();
+    writeln!(code, "// Generated by lexer-vm, do not edit").unwrap();
+    writeln!(code).unwrap();
+    write_token_enum(&mut code, TOKEN_ENUM_NAME, &token_names(definition), &variants).unwrap();
+    write_rust(&mut code, &table, &variants).unwrap();
+    Ok(code)
+}
+
+/// Generates `#[repr(u16)]` enum of token types of the definition, including `ERROR` and `END` ones.
+/// Discriminants are token type indices, enum has `from_index`, `index` and `name` methods and implements `Display`
+pub fn generate_token_enum(definition: &LexerDefinition, enum_name: &str) -> String {
+    let mut code = String::new();
+    write_token_enum(&mut code, enum_name, &token_names(definition), &variant_names(definition)).unwrap();
+    code
+}
+
+/// Token type names by token type index, None for indices without token
+fn token_names(definition: &LexerDefinition) -> Vec<Option<&str>> {
+    let mut names: Vec<Option<&str>> = vec![None; 2];
+    names[ERROR_TOKEN_INDEX as usize] = Some(ERROR_TOKEN_NAME);
+    names[END_TOKEN_INDEX as usize] = Some(END_TOKEN_NAME);
+    for token in definition.all_tokens() {
+        let index = token.index as usize;
+        if names.len() <= index {
+            names.resize(index + 1, None);
+        }
+        names[index] = Some(&token.name);
+    }
+    names
+}
+
+/// Enum variant names by token type index, None for indices without token
+fn variant_names(definition: &LexerDefinition) -> Vec<Option<String>> {
+    let mut used = HashSet::new();
+    token_names(definition).iter().enumerate()
+        .map(|(index, name)| name.map(|name| {
+            let mut variant = variant_name(name);
+            if used.contains(&variant) {
+                variant = format!("{}{}", variant, index);
+            }
+            used.insert(variant.clone());
+            variant
+        }))
+        .collect()
+}
+
+/// CamelCase identifier from the token name, e.g. `LINE_COMMENT` becomes `LineComment`
+pub fn variant_name(name: &str) -> String {
+    let mut variant = String::new();
+    for part in name.split(|ch: char| !ch.is_ascii_alphanumeric()).filter(|part| !part.is_empty()) {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            variant.push(first.to_ascii_uppercase());
+            variant.extend(chars.map(|ch| ch.to_ascii_lowercase()));
+        }
+    }
+    if variant.is_empty() || variant.starts_with(|ch: char| ch.is_ascii_digit()) {
+        variant.insert(0, 'T');
+    }
+    if variant == "Self" {
+        variant.push('_');
+    }
+    variant
+}
+
+fn write_token_enum<W: Write>(out: &mut W, enum_name: &str, names: &[Option<&str>], variants: &[Option<String>]) -> fmt::Result {
+    let entries: Vec<(usize, &str, &str)> = names.iter().zip(variants).enumerate()
+        .filter_map(|(index, (name, variant))| match (name, variant) {
+            (Some(name), Some(variant)) => Some((index, *name, variant.as_str())),
+            _ => None
+        })
+        .collect();
+    writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]")?;
+    writeln!(out, "#[repr(u16)]")?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "pub enum {} {{", enum_name)?;
+    for &(index, _, variant) in &entries {
+        writeln!(out, "    {} = {},", variant, index)?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "impl {} {{", enum_name)?;
+    writeln!(out, "    /// Token type by token type index, None if there is no such token type")?;
+    writeln!(out, "    pub fn from_index(index: u16) -> Option<{}> {{", enum_name)?;
+    writeln!(out, "        match index {{")?;
+    for &(index, _, variant) in &entries {
+        writeln!(out, "            {} => Some({}::{}),", index, enum_name, variant)?;
+    }
+    writeln!(out, "            _ => None,")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "    }}")?;
+    writeln!(out)?;
+    writeln!(out, "    pub fn index(self) -> u16 {{")?;
+    writeln!(out, "        self as u16")?;
+    writeln!(out, "    }}")?;
+    writeln!(out)?;
+    writeln!(out, "    /// Name of the token type in the lexer definition")?;
+    writeln!(out, "    pub fn name(self) -> &'static str {{")?;
+    writeln!(out, "        match self {{")?;
+    for &(_, name, variant) in &entries {
+        writeln!(out, "            {}::{} => {:?},", enum_name, variant, name)?;
+    }
+    writeln!(out, "        }}")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "impl ::std::fmt::Display for {} {{", enum_name)?;
+    writeln!(out, "    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {{")?;
+    writeln!(out, "        f.write_str(self.name())")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")
+}
+
+fn write_rust<W: Write>(out: &mut W, table: &DfaTable, variants: &[Option<String>]) -> fmt::Result {
+    // transitions are state ids, so the greatest one must fit
+    let max_state = table.state_count().saturating_sub(1);
+    let state_type = if max_state <= u8::MAX as usize {
+        "u8"
+    } else if max_state <= u16::MAX as usize {
+        "u16"
+    } else {
+        "u32"
+    };
+    let error = variants[ERROR_TOKEN_INDEX as usize].as_ref().unwrap();
+    let end = variants[END_TOKEN_INDEX as usize].as_ref().unwrap();
+    writeln!(out)?;
+    writeln!(out, "/// Token type and byte offsets of the token in the text")?;
+    writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]")?;
+    writeln!(out, "pub struct Token {{")?;
+    writeln!(out, "    pub token_type: TokenType,")?;
+    writeln!(out, "    pub start: usize,")?;
+    writeln!(out, "    pub end: usize,")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "const DEAD_STATE: usize = {};", DEAD_STATE)?;
+    writeln!(out, "const START_STATE: usize = {};", START_STATE)?;
+    writeln!(out, "const CLASS_COUNT: usize = {};", table.class_count())?;
+    write_array(out, "CLASS_STARTS", "u32", &table.class_starts)?;
+    let classes = CharClasses::new(table.class_starts.clone());
+    let ascii_classes: Vec<usize> = (0..128u8).map(|code_point| classes.class_of(code_point as char)).collect();
+    write_array(out, "ASCII_CLASSES", "u8", &ascii_classes)?;
+    write_array(out, "TRANSITIONS", state_type, &table.transitions)?;
+    let accepts: Vec<String> = table.accepts.iter()
+        .map(|accept| match *accept {
+            Some(token_type_index) => match variants.get(token_type_index as usize) {
+                Some(Some(variant)) => format!("Some(TokenType::{})", variant),
+                _ => "None".to_string(),
+            },
+            None => "None".to_string(),
+        })
+        .collect();
+    write_array(out, "ACCEPTS", "Option<TokenType>", &accepts)?;
+    write!(out, r#"
+fn class_of(ch: char) -> usize {{
+    let code_point = ch as u32;
+    if code_point < 128 {{
+        return ASCII_CLASSES[code_point as usize] as usize;
+    }}
+    match CLASS_STARTS.binary_search(&code_point) {{
+        Ok(class) => class,
+        Err(next_class) => next_class - 1,
+    }}
+}}
+
+/// Longest match at `start`: token type and end
+fn longest_match(text: &str, start: usize) -> Option<(TokenType, usize)> {{
+    let mut state = START_STATE;
+    let mut best = None;
+    for (offset, ch) in text[start..].char_indices() {{
+        state = TRANSITIONS[state * CLASS_COUNT + class_of(ch)] as usize;
+        if state == DEAD_STATE {{
+            break;
+        }}
+        if let Some(token_type) = ACCEPTS[state] {{
+            best = Some((token_type, start + offset + ch.len_utf8()));
+        }}
+    }}
+    best
+}}
+
+/// Lazily splits text into tokens, finishing with zero length `TokenType::{end}` token.
+/// Text, which doesn't match any token, is reported as `TokenType::{error}` tokens
+pub fn tokenize<'a>(text: &'a str) -> Lexer<'a> {{
+    Lexer {{ text, position: 0, is_end: false, lookahead: None }}
+}}
+
+pub struct Lexer<'a> {{
+    text: &'a str,
+    position: usize,
+    is_end: bool,
+    lookahead: Option<(TokenType, usize)>,
+}}
+
+impl<'a> Iterator for Lexer<'a> {{
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {{
+        if self.is_end {{
+            return None;
+        }}
+        let start = self.position;
+        if start == self.text.len() {{
+            self.is_end = true;
+            return Some(Token {{ token_type: TokenType::{end}, start, end: start }});
+        }}
+        let best = match self.lookahead.take() {{
+            Some(best) => Some(best),
+            None => longest_match(self.text, start),
+        }};
+        let (token_type, end) = match best {{
+            Some(best) => best,
+            None => {{
+                // error token lasts until some token matches
+                let mut end = next_char_end(self.text, start);
+                while end < self.text.len() {{
+                    if let Some(best) = longest_match(self.text, end) {{
+                        self.lookahead = Some(best);
+                        break;
+                    }}
+                    end = next_char_end(self.text, end);
+                }}
+                (TokenType::{error}, end)
+            }}
+        }};
+        self.position = end;
+        Some(Token {{ token_type, start, end }})
+    }}
+}}
+
+fn next_char_end(text: &str, offset: usize) -> usize {{
+    offset + text[offset..].chars().next().map(|ch| ch.len_utf8()).unwrap_or(0)
+}}
+"#, end = end, error = error)
+}
+
+fn write_array<W: Write, T: fmt::Display>(out: &mut W, name: &str, element_type: &str, elements: &[T]) -> fmt::Result {
+    writeln!(out, "static {}: [{}; {}] = [", name, element_type, elements.len())?;
+    for line in elements.chunks(ELEMENTS_PER_LINE) {
+        let line: Vec<String> = line.iter().map(|element| element.to_string()).collect();
+        writeln!(out, "    {},", line.join(", "))?;
+    }
+    writeln!(out, "];")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::TokenDefinition;
+    use ast::TieBreak;
+    use lexer::Lexer;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    /// Path of the module, generated from `fixture_definition`, relative to the crate root
+    const FIXTURE_PATH: &str = "tests/fixtures/generated_lexer.rs";
+    /// Path of the module, generated from `wide_fixture_definition`
+    const WIDE_FIXTURE_PATH: &str = "tests/fixtures/generated_wide_lexer.rs";
+
+    /// Lexer of the generated fixture module, `fixture_is_current` keeps the module up to date
+    fn fixture_definition() -> LexerDefinition {
+        LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "IF", "if").unwrap().with_priority(1),
+            TokenDefinition::from_pattern(3, "IDENT", "[a-zA-Z_\u{3b1}-\u{3c9}][a-zA-Z0-9_\u{3b1}-\u{3c9}]*").unwrap(),
+            TokenDefinition::from_pattern(4, "NUMBER", r"[0-9]+(\.[0-9]+)?").unwrap(),
+            TokenDefinition::from_pattern(5, "STRING", r#""([^"\\]|\\.)*""#).unwrap(),
+            TokenDefinition::from_pattern(6, "WS", "[ \t\n]+").unwrap(),
+            TokenDefinition::from_pattern(7, "LINE_COMMENT", "//.*").unwrap(),
+            TokenDefinition::from_pattern(8, "ARROW", "->").unwrap(),
+            TokenDefinition::from_pattern(9, "MINUS", "-").unwrap(),
+            TokenDefinition::from_pattern(10, "SLASH", "/").unwrap(),
+        ]).with_tie_break(TieBreak::Priority)
+    }
+
+    /// Lexer of the generated fixture module with more than 256 states, so its transitions are `u16`
+    fn wide_fixture_definition() -> LexerDefinition {
+        LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "WORD", "a+").unwrap(),
+            TokenDefinition::from_pattern(3, "LONG_WORD", "a{300}").unwrap(),
+            TokenDefinition::from_pattern(4, "WS", " +").unwrap(),
+        ])
+    }
+
+    #[allow(dead_code)]
+    mod generated {
+        include!("../tests/fixtures/generated_lexer.rs");
+    }
+
+    #[allow(dead_code)]
+    mod generated_wide {
+        include!("../tests/fixtures/generated_wide_lexer.rs");
+    }
+
+    /// Generated code of the fixture at `path` is up to date, the file is rewritten only if it is outdated
+    /// and `UPDATE_FIXTURES` is set
+    fn check_fixture(definition: &LexerDefinition, path: &str) -> String {
+        let code = generate_rust(definition).unwrap();
+        let full_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(path);
+        let is_current = fs::read_to_string(&full_path).map(|fixture| fixture == code).unwrap_or(false);
+        if !is_current && env::var_os("UPDATE_FIXTURES").is_some() {
+            fs::write(&full_path, &code).unwrap();
+            return code;
+        }
+        assert!(is_current, "{} is outdated, run tests with UPDATE_FIXTURES=1", path);
+        code
+    }
+
+    /// Compares tokens of the generated module with tokens of `Lexer`
+    fn check_generated<F: Fn(&str) -> Vec<(u16, usize, usize)>>(definition: &LexerDefinition, tokenize: F, texts: &[&str]) {
+        let lexer = Lexer::new(definition).unwrap();
+        for text in texts {
+            let expected: Vec<(u16, usize, usize)> = lexer.tokens(text)
+                .map(|token| (token.token_type_index(), token.start(), token.end()))
+                .collect();
+            assert_eq!(expected, tokenize(text), "text: {:?}", text);
+        }
+    }
+
+    /// Run with `UPDATE_FIXTURES=1` to regenerate fixtures after codegen changes
+    #[test]
+    fn fixture_is_current() {
+        let code = check_fixture(&fixture_definition(), FIXTURE_PATH);
+        assert!(code.contains("static TRANSITIONS: [u8; "));
+        let code = check_fixture(&wide_fixture_definition(), WIDE_FIXTURE_PATH);
+        assert!(code.contains("static TRANSITIONS: [u16; "));
+    }
+
+    #[test]
+    fn generated_lexer_matches_vm() {
+        let texts = [
+            "if iffy x1 -> y - 2.5 / 3.",
+            "\"a \\\" b\" // comment \u{3b1}\n\u{3b2}\u{3b3} if",
+            "?? 12 \u{1F600}\u{1F600} \"unterminated",
+            "",
+            "\n\t a--b//",
+        ];
+        check_generated(&fixture_definition(), |text| {
+            generated::tokenize(text).map(|token| (token.token_type.index(), token.start, token.end)).collect()
+        }, &texts);
+    }
+
+    #[test]
+    fn generated_wide_lexer_matches_vm() {
+        let texts = [
+            "a".repeat(299) + " " + &"a".repeat(300) + " " + &"a".repeat(301),
+            "a".repeat(600) + "?a",
+        ];
+        let texts: Vec<&str> = texts.iter().map(|text| text.as_str()).collect();
+        check_generated(&wide_fixture_definition(), |text| {
+            generated_wide::tokenize(text).map(|token| (token.token_type.index(), token.start, token.end)).collect()
+        }, &texts);
+    }
+
+    #[test]
+    fn variant_names() {
+        assert_eq!("LineComment", variant_name("LINE_COMMENT"));
+        assert_eq!("KwIf", variant_name("kw-if"));
+        assert_eq!("T1st", variant_name("1st"));
+        assert_eq!("T", variant_name("+"));
+        assert_eq!("Self_", variant_name("SELF"));
+    }
+
+    #[test]
+    fn token_enum() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "NUM", "[0-9]+").unwrap(),
+            TokenDefinition::from_pattern(5, "LINE_COMMENT", "#.*").unwrap(),
+        ]);
+        let code = generate_token_enum(&definition, "Kind");
+        assert!(code.contains("#[repr(u16)]\n#[allow(dead_code)]\npub enum Kind {\n    Error = 0,\n    End = 1,\n    Num = 2,\n    LineComment = 5,\n}"));
+        assert!(code.contains("            5 => Some(Kind::LineComment),\n            _ => None,"));
+        assert!(code.contains("            Kind::End => \"END\",\n"));
+        assert!(code.contains("            Kind::LineComment => \"LINE_COMMENT\",\n"));
+        assert!(code.contains("impl ::std::fmt::Display for Kind {"));
+    }
+
+    #[test]
+    fn generate() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "NUM", "[0-9]+").unwrap(),
+            TokenDefinition::from_pattern(3, "error", "!").unwrap(),
+        ]);
+        let code = generate_rust(&definition).unwrap();
+        assert!(code.contains("pub enum TokenType {\n    Error = 0,\n    End = 1,\n    Num = 2,\n    Error3 = 3,\n}"));
+        assert!(code.contains("pub fn tokenize"));
+        assert!(code.contains("static CLASS_STARTS: [u32; 5] = [\n    0, 33, 34, 48, 58,\n];"));
+        // dead, start, after digits, after !
+        assert!(code.contains("static ACCEPTS: [Option<TokenType>; 4] = [\n    None, None, "));
+        assert!(code.contains("Some(TokenType::Num)") && code.contains("Some(TokenType::Error3)"));
+    }
+}
@@ -1,12 +1,74 @@
 use vm::Vm;
+use vm::TokenAction;
 use ast::Expr;
 use assembler::Assembler;
 use assembler::ProgramData;
+use assembler::EncodingError;
+use assembler::JmpMarker;
+use assembler::CodePointer;
+use debugger::DebugInfo;
+use debugger::TokenCode;
 use ast::LexerDefinition;
 use ast::TokenDefinition;
+use ast::TieBreak;
+use ast::ModeAction;
+use ast::INITIAL_MODE_NAME;
+use ast::complement;
+use utf8::utf8_sequences;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::cmp::max;
+use std::cmp::Reverse;
+use std::mem;
 
-struct Compiler {
-    asm: Assembler
+/// Default limit for the bounds of counted repetitions (`{n,m}`)
+pub const DEFAULT_REPETITION_LIMIT: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    /// Counted repetition bound, multiplied by bounds of enclosing repetitions, exceeds configured limit
+    RepetitionTooLarge { count: u32, limit: u32 },
+    /// Program doesn't fit into bytecode encoding
+    Encoding(EncodingError),
+    /// Token action refers to the mode, which is not defined
+    UnknownMode { name: String },
+    /// Several modes have the same name
+    DuplicateMode { name: String },
+}
+
+impl From<EncodingError> for CompileError {
+    fn from(error: EncodingError) -> Self {
+        CompileError::Encoding(error)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CompileError::RepetitionTooLarge { count, limit } =>
+                write!(f, "repetition count {} exceeds limit {}", count, limit),
+            CompileError::Encoding(error) => write!(f, "{}", error),
+            CompileError::UnknownMode { name } => write!(f, "unknown mode {}", name),
+            CompileError::DuplicateMode { name } => write!(f, "mode {} is defined more than once", name),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+pub struct Compiler {
+    asm: Assembler,
+    repetition_limit: u32,
+    /// Number of copies of the current expression, unrolled by enclosing repetitions
+    repetition_factor: u32,
+    /// Chars are lowered into UTF-8 byte sequences, see `with_byte_mode`
+    byte_mode: bool,
+    token_ranks: Vec<u32>,
+    token_actions: Vec<Vec<TokenAction>>,
+    /// Code ranges of token variants of the lexer, in the order of definition
+    token_code: Vec<(CodePointer, CodePointer)>,
+    debug_info: DebugInfo,
 }
 
 impl Default for Compiler {
@@ -17,118 +79,376 @@ impl Default for Compiler {
 
 impl Compiler {
     pub fn new() -> Self {
-        Compiler { asm: Assembler::new() }
+        Compiler { asm: Assembler::new(), repetition_limit: DEFAULT_REPETITION_LIMIT, repetition_factor: 1, byte_mode: false, token_ranks: Vec::new(), token_actions: Vec::new(), token_code: Vec::new(), debug_info: DebugInfo::default() }
     }
 
-    pub fn compile_lexer(&mut self, lexer_definition: &LexerDefinition) -> Vm {
-        self.generate_lexer(lexer_definition);
+    /// Sets maximal allowed bound of counted repetition, every repetition is unrolled in bytecode,
+    /// so bounds of nested repetitions are multiplied
+    pub fn with_repetition_limit(mut self, limit: u32) -> Self {
+        self.repetition_limit = limit;
+        self
+    }
+
+    /// Compiles every char of patterns into the automaton of its UTF-8 bytes, so the program lexes `&[u8]`
+    /// byte by byte, see `Vm::tokenize_bytes`. Bytes, which are not valid UTF-8, become error tokens
+    pub fn with_byte_mode(mut self, byte_mode: bool) -> Self {
+        self.byte_mode = byte_mode;
+        self
+    }
+
+    pub fn compile_lexer(&mut self, lexer_definition: &LexerDefinition) -> Result<Vm, CompileError> {
+        self.generate_lexer(lexer_definition)?;
         self.get_vm()
     }
 
-    pub fn get_vm(&mut self) -> Vm {
-        let program_data = self.get_prog_data();
-        Vm::new(program_data.code, program_data.constant_pool)
+    pub fn get_vm(&mut self) -> Result<Vm, CompileError> {
+        let program_data = self.get_prog_data()?;
+        Ok(Vm::new(program_data.code, program_data.constant_pool)
+            .with_token_ranks(program_data.token_ranks)
+            .with_token_actions(program_data.token_actions)
+            .with_byte_mode(program_data.byte_mode)
+            .with_debug_info(program_data.debug_info))
     }
 
-    pub fn get_prog_data(&mut self) -> ProgramData {
-        self.asm.finish()
+    pub fn get_prog_data(&mut self) -> Result<ProgramData, CompileError> {
+        let mut program_data = self.asm.finish()?;
+        program_data.token_ranks = mem::take(&mut self.token_ranks);
+        program_data.debug_info = mem::take(&mut self.debug_info);
+        program_data.token_actions = mem::take(&mut self.token_actions);
+        program_data.byte_mode = self.byte_mode;
+        Ok(program_data)
     }
 
-    pub fn generate_lexer(&mut self, definition: &LexerDefinition) {
-        let indices: Vec<u16> = definition.tokens.iter()
+    /// Lexer with modes starts with OuterLexer, which refers to the code of every mode
+    pub fn generate_lexer(&mut self, definition: &LexerDefinition) -> Result<(), CompileError> {
+        self.token_ranks = token_ranks(definition);
+        self.token_actions = token_actions(definition)?;
+        self.token_code.clear();
+        if definition.modes.is_empty() {
+            self.generate_mode(&definition.tokens)?;
+        } else {
+            let marker = self.asm.emit_outer_lexer();
+            let mut entries = Vec::new();
+            entries.push(self.asm.next_code_position());
+            self.generate_mode(&definition.tokens)?;
+            for mode in &definition.modes {
+                entries.push(self.asm.next_code_position());
+                self.generate_mode(&mode.tokens)?;
+            }
+            self.asm.patch_outer_lexer(&marker, entries);
+        }
+        let tokens = definition.all_tokens()
+            .zip(self.token_code.drain(..))
+            .map(|(token, (start, end))| TokenCode {
+                start,
+                end,
+                token_type_index: token.index,
+                name: token.name.clone(),
+                expr: token.expr.clone(),
+            })
+            .collect();
+        self.debug_info = DebugInfo::new(tokens);
+        Ok(())
+    }
+
+    fn generate_mode(&mut self, tokens: &[TokenDefinition]) -> Result<(), CompileError> {
+        let indices: Vec<u16> = tokens.iter()
             .map(|el| el.index)
             .collect();
-        let expressions : Vec<Expr> = definition.tokens.iter()
+        let expressions : Vec<Expr> = tokens.iter()
             .map(|el| el.expr.clone())
             .collect();
         self.generate_or(&expressions, Some(indices))
     }
 
-    pub fn generate_token_expr(&mut self, definition: &TokenDefinition) {
-        self.generate(&definition.expr);
-        self.asm.emit_match(definition.index)
+    pub fn generate_token_expr(&mut self, definition: &TokenDefinition) -> Result<(), CompileError> {
+        self.generate(&definition.expr)?;
+        self.asm.emit_match(definition.index);
+        Ok(())
     }
 
-    pub fn generate(&mut self, expr: &Expr) {
+    pub fn generate(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        if self.byte_mode {
+            if let Some(ranges) = char_ranges(expr) {
+                self.generate_utf8(&ranges);
+                return Ok(());
+            }
+        }
         match expr {
             Expr::Single { ch } => {
-                self.asm.emit_char_imm(*ch);
+                self.asm.emit_char(*ch);
             },
             Expr::Range { from, to } => {
-                self.asm.emit_range_imm(*from, *to);
+                self.asm.emit_range(*from, *to);
             },
             Expr::Or { variants } => {
-                self.generate_or(variants, None)
+                self.generate_or(variants, None)?;
             },
             Expr::Seq { exprs } => {
                 for e in exprs {
-                    self.generate(e);
+                    self.generate(e)?;
                 }
             },
             Expr::Loop { expr } => {
-                let (forward_to_loop_start, forward_to_after_loop) = self.asm.emit_split(0, 0);
-                let loop_start = self.asm.next_code_position();
-                self.generate(expr);
-                let (_, after_loop_mk) = self.asm.emit_split(loop_start, 0);
-                let after_loop = self.asm.next_code_position();
-                self.asm.patch_target(&forward_to_after_loop, after_loop);
-                self.asm.patch_target(&after_loop_mk, after_loop);
-                self.asm.patch_target(&forward_to_loop_start, loop_start);
+                self.generate_loop(expr)?;
             }
+            Expr::Repeat { expr, min, max } => {
+                self.generate_repeat(expr, *min, *max)?;
+            }
+            Expr::Class { ranges, negated } => {
+                self.generate_class(ranges, *negated)?;
+            }
+            Expr::Any { matches_newline } => {
+                self.asm.emit_any(*matches_newline);
+            }
+        }
+        Ok(())
+    }
+
+    fn generate_loop(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        let (forward_to_loop_start, forward_to_after_loop) = self.asm.emit_split(0, 0);
+        let loop_start = self.asm.next_code_position();
+        self.generate(expr)?;
+        let (_, after_loop_mk) = self.asm.emit_split(loop_start, 0);
+        let after_loop = self.asm.next_code_position();
+        self.asm.patch_target(&forward_to_after_loop, after_loop);
+        self.asm.patch_target(&after_loop_mk, after_loop);
+        self.asm.patch_target(&forward_to_loop_start, loop_start);
+        Ok(())
+    }
+
+    /// Unrolls `expr{min,max}` into `min` mandatory copies followed either by loop (if unbounded)
+    /// or by `max - min` optional copies, each of them can skip to the end
+    fn generate_repeat(&mut self, expr: &Expr, min: u32, max: Option<u32>) -> Result<(), CompileError> {
+        let count = self.repetition_factor.saturating_mul(max.unwrap_or(min));
+        if count > self.repetition_limit {
+            return Err(CompileError::RepetitionTooLarge { count, limit: self.repetition_limit });
+        }
+        let outer_factor = mem::replace(&mut self.repetition_factor, count.max(1));
+        let result = self.generate_repeat_copies(expr, min, max);
+        self.repetition_factor = outer_factor;
+        result
+    }
+
+    fn generate_repeat_copies(&mut self, expr: &Expr, min: u32, max: Option<u32>) -> Result<(), CompileError> {
+        for _ in 0..min {
+            self.generate(expr)?;
         }
+        match max {
+            None => self.generate_loop(expr)?,
+            Some(max) => {
+                let mut to_end_markers = Vec::new();
+                for _ in min..max {
+                    let (to_next, to_end) = self.asm.emit_split(0, 0);
+                    let next = self.asm.next_code_position();
+                    self.asm.patch_target(&to_next, next);
+                    to_end_markers.push(to_end);
+                    self.generate(expr)?;
+                }
+                let end = self.asm.next_code_position();
+                for marker in &to_end_markers {
+                    self.asm.patch_target(marker, end);
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn generate_or(&mut self, variants: &Vec<Expr>, type_indices: Option<Vec<u16>>) -> () {
+    /// Negated class is emitted as Invert followed by its members,
+    /// plain class is just an alternative of its ranges
+    fn generate_class(&mut self, ranges: &[(char, char)], negated: bool) -> Result<(), CompileError> {
+        let members: Vec<Expr> = ranges.iter()
+            .map(|&(from, to)| if from == to { Expr::Single { ch: from } } else { Expr::Range { from, to } })
+            .collect();
+        if !negated {
+            return self.generate_or(&members, None);
+        }
+        self.asm.emit_invert(members.len());
+        for member in &members {
+            self.generate(member)?;
+        }
+        Ok(())
+    }
+
+    /// Alternative of byte sequences, which encode chars of the ranges, empty alternative never matches
+    fn generate_utf8(&mut self, ranges: &[(char, char)]) {
+        let sequences: Vec<_> = ranges.iter()
+            .flat_map(|&(from, to)| utf8_sequences(from, to))
+            .collect();
+        if sequences.len() == 1 {
+            self.generate_byte_sequence(&sequences[0]);
+            return;
+        }
+        let mut to_end_markers = Vec::new();
+        let mut positions = Vec::new();
+        let marker = self.asm.emit_split_many();
+        for (index, sequence) in sequences.iter().enumerate() {
+            positions.push(self.asm.next_code_position());
+            self.generate_byte_sequence(sequence);
+            if index + 1 != sequences.len() {
+                to_end_markers.push(self.asm.emit_jmp(0));
+            }
+        }
+        self.asm.patch_split_many(&marker, positions);
+        self.patch_to_end(&to_end_markers);
+    }
+
+    /// Byte is matched as the char with the same code point
+    fn generate_byte_sequence(&mut self, sequence: &[(u8, u8)]) {
+        for &(from, to) in sequence {
+            if from == to {
+                self.asm.emit_char(from as char);
+            } else {
+                self.asm.emit_range(from as char, to as char);
+            }
+        }
+    }
+
+    fn generate_or(&mut self, variants: &[Expr], type_indices: Option<Vec<u16>>) -> Result<(), CompileError> {
+        let mut to_end_markers = Vec::new();
         match variants.len() {
             1 => {
-                self.generate(&variants[0]);
-                if let Some(ref indices) = type_indices {
-                    self.asm.emit_match(indices[0])
-                }
+                let start = self.asm.next_code_position();
+                self.generate(&variants[0])?;
+                self.finish_variant(start, 0, 1, &type_indices, &mut to_end_markers);
             }
             2 => {
                 let left = &variants[0];
                 let right = &variants[1];
-                self.generate_split(left, right, type_indices)
+                self.generate_split(left, right, type_indices)?;
             }
             _ => {
                 let mut positions = Vec::new();
                 let marker = self.asm.emit_split_many();
                 for (index, variant) in variants.iter().enumerate() {
-                    positions.push(self.asm.next_code_position());
-                    self.generate(variant);
-                    if let Some(ref indices) = type_indices {
-                        self.asm.emit_match(indices[index])
-                    }
+                    let start = self.asm.next_code_position();
+                    positions.push(start);
+                    self.generate(variant)?;
+                    self.finish_variant(start, index, variants.len(), &type_indices, &mut to_end_markers);
                 }
                 self.asm.patch_split_many(&marker, positions);
             }
         }
+        self.patch_to_end(&to_end_markers);
+        Ok(())
     }
 
-    fn generate_split(&mut self, left: &Expr, right: &Expr, type_indices: Option<Vec<u16>>) {
+    fn generate_split(&mut self, left: &Expr, right: &Expr, type_indices: Option<Vec<u16>>) -> Result<(), CompileError> {
+        let mut to_end_markers = Vec::new();
         let (left_patch, right_patch) = self.asm.emit_split(0, 0);
         let left_target = self.asm.next_code_position();
-        self.generate(left);
-        if let Some(ref indices) = type_indices {
-            self.asm.emit_match(indices[0])
-        }
+        self.generate(left)?;
+        self.finish_variant(left_target, 0, 2, &type_indices, &mut to_end_markers);
         let right_target = self.asm.next_code_position();
-        self.generate(right);
-        if let Some(ref indices) = type_indices {
-            self.asm.emit_match(indices[1])
-        }
+        self.generate(right)?;
+        self.finish_variant(right_target, 1, 2, &type_indices, &mut to_end_markers);
         self.asm.patch_target(&left_patch, left_target);
         self.asm.patch_target(&right_patch, right_target);
+        self.patch_to_end(&to_end_markers);
+        Ok(())
+    }
+
+    /// Token variant ends with Match, other variants (except the last one) jump over the following ones
+    fn finish_variant(&mut self, start: CodePointer, index: usize, count: usize, type_indices: &Option<Vec<u16>>, to_end_markers: &mut Vec<JmpMarker>) {
+        if let Some(ref indices) = type_indices {
+            self.asm.emit_match(indices[index]);
+            let end = self.asm.next_code_position();
+            self.token_code.push((start, end));
+        } else if index + 1 != count {
+            to_end_markers.push(self.asm.emit_jmp(0));
+        }
+    }
+
+    fn patch_to_end(&mut self, to_end_markers: &[JmpMarker]) {
+        let end = self.asm.next_code_position();
+        for marker in to_end_markers {
+            self.asm.patch_jmp(marker, end);
+        }
     }
 }
 
+/// Chars, matched by the single char expression, None for other expressions
+fn char_ranges(expr: &Expr) -> Option<Vec<(char, char)>> {
+    match *expr {
+        Expr::Single { ch } => Some(vec![(ch, ch)]),
+        Expr::Range { from, to } => Some(vec![(from, to)]),
+        Expr::Class { ref ranges, negated: false } => Some(ranges.clone()),
+        Expr::Class { ref ranges, negated: true } => Some(complement(ranges)),
+        Expr::Any { matches_newline: true } => Some(complement(&[])),
+        Expr::Any { matches_newline: false } => Some(complement(&[('\n', '\n')])),
+        _ => None,
+    }
+}
+
+/// Ranks token types according to `TieBreak` of the definition, greater rank wins
+fn token_ranks(definition: &LexerDefinition) -> Vec<u32> {
+    if definition.tie_break == TieBreak::HighestIndex {
+        // `Vm` ranks token types by index, when no ranks are given
+        return Vec::new();
+    }
+    let tokens: Vec<&TokenDefinition> = definition.all_tokens().collect();
+    let mut order: Vec<usize> = (0..tokens.len()).collect();
+    // from the weakest to the strongest
+    order.sort_by_key(|&position| {
+        let priority = match definition.tie_break {
+            TieBreak::HighestIndex | TieBreak::FirstDefined => 0,
+            TieBreak::Priority => tokens[position].priority,
+        };
+        (priority, Reverse(position))
+    });
+    let max_index = tokens.iter().map(|token| token.index as usize).max().unwrap_or(0);
+    let mut ranks = vec![0; max_index + 1];
+    for (rank, &position) in order.iter().enumerate() {
+        let index = tokens[position].index as usize;
+        ranks[index] = max(ranks[index], rank as u32 + 1);
+    }
+    ranks
+}
+
+/// Resolves mode names of token actions, actions are indexed by mode and then by token type index
+fn token_actions(definition: &LexerDefinition) -> Result<Vec<Vec<TokenAction>>, CompileError> {
+    let mut names = vec![INITIAL_MODE_NAME];
+    for mode in &definition.modes {
+        if names.contains(&mode.name.as_str()) {
+            return Err(CompileError::DuplicateMode { name: mode.name.clone() });
+        }
+        names.push(&mode.name);
+    }
+    let mode_index = |name: &str| names.iter()
+        .position(|&mode_name| mode_name == name)
+        .map(|index| index as u16)
+        .ok_or_else(|| CompileError::UnknownMode { name: name.to_string() });
+    let modes = Some(&definition.tokens).into_iter().chain(definition.modes.iter().map(|mode| &mode.tokens));
+    let mut actions = Vec::new();
+    for tokens in modes {
+        let mut mode_actions = Vec::new();
+        for token in tokens {
+            let action = match token.action {
+                ModeAction::None => TokenAction::None,
+                ModeAction::Push(ref name) => TokenAction::Push(mode_index(name)?),
+                ModeAction::Pop => TokenAction::Pop,
+                ModeAction::Switch(ref name) => TokenAction::Switch(mode_index(name)?),
+            };
+            let index = token.index as usize;
+            if mode_actions.len() <= index {
+                mode_actions.resize(index + 1, TokenAction::None);
+            }
+            mode_actions[index] = action;
+        }
+        actions.push(mode_actions);
+    }
+    Ok(actions)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use disasm::decode;
     use disasm::Instruction;
     use disasm::Instruction::*;
+    use ast::ModeDefinition;
+    use vm::TokenRaw;
+    use vm::END_TOKEN_INDEX;
 
 
     #[test]
@@ -144,20 +464,15 @@ mod tests {
                 ]
             }
         ] };
-        let lexer_definition = LexerDefinition {
-            tokens: vec![
-                TokenDefinition {
-                    expr,
-                    index: 2,
-                    name: "foo".to_string()
-                }
-            ]
-        };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
         check_compiler(&mut compiler, &lexer_definition, vec![
             CharImm { ch: 'a' },
             CharImm { ch: 'b' },
-            Split { then_instr_index: 3, else_instr_index: 4 },
+            Split { then_instr_index: 3, else_instr_index: 5 },
             CharImm { ch: 'c' },
+            Jmp { instr_index: 6 },
             CharImm { ch: 'd' },
             Match { token_type_index: 2 }
         ], vec![]);
@@ -170,21 +485,32 @@ mod tests {
             from: 'a',
             to: 'z',
         };
-        let lexer_definition = LexerDefinition {
-            tokens: vec![
-                TokenDefinition {
-                    expr,
-                    index: 2,
-                    name: "foo".to_string()
-                }
-            ]
-        };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
         check_compiler(&mut compiler, &lexer_definition, vec![
             RangeImm { from: 'a', to: 'z' },
             Match { token_type_index: 2 }
         ], vec![]);
     }
 
+    #[test]
+    fn range_cp() {
+        let mut compiler = Compiler::new();
+        let expr = Expr::Seq { exprs: vec![
+            Expr::Range { from: '\u{4E00}', to: '\u{9FFF}' },
+            Expr::Range { from: 'a', to: '\u{9FFF}' },
+        ]};
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
+        check_compiler(&mut compiler, &lexer_definition, vec![
+            Range { from_index: 0, to_index: 1 },
+            Range { from_index: 2, to_index: 1 },
+            Match { token_type_index: 2 }
+        ], vec![0x4E00, 0x9FFF, 'a' as u32]);
+    }
+
     #[test]
     fn multiple_tokens(){
         let mut compiler = Compiler::new();
@@ -196,20 +522,10 @@ mod tests {
             Expr::Single { ch: 'c' },
             Expr::Single { ch: 'd' },
         ]};
-        let lexer_definition = LexerDefinition {
-            tokens: vec![
-                TokenDefinition {
-                    expr: first,
-                    index: 2,
-                    name: "foo".to_string()
-                },
-                TokenDefinition {
-                    expr: second,
-                    index: 3,
-                    name: "bar".to_string()
-                }
-            ]
-        };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", first),
+            TokenDefinition::new(3, "bar", second)
+        ]);
         check_compiler(&mut compiler, &lexer_definition, vec![
             Split { then_instr_index: 1, else_instr_index: 4 },
             CharImm { ch: 'a' },
@@ -225,15 +541,9 @@ mod tests {
     fn compile_loop(){
         let mut compiler = Compiler::new();
         let expr = Expr::Loop { expr: Box::new(Expr::Single { ch: 'a' })};
-        let lexer_definition = LexerDefinition {
-            tokens: vec![
-                TokenDefinition {
-                    expr,
-                    index: 2,
-                    name: "foo".to_string()
-                }
-            ]
-        };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
         check_compiler(&mut compiler, &lexer_definition, vec![
             Split { then_instr_index: 1, else_instr_index: 3 },
             CharImm { ch: 'a' },
@@ -242,9 +552,214 @@ mod tests {
         ], vec![]);
     }
 
+    #[test]
+    fn compile_bounded_repeat() {
+        let mut compiler = Compiler::new();
+        let expr = Expr::Repeat { expr: Box::new(Expr::Single { ch: 'a' }), min: 1, max: Some(3) };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
+        check_compiler(&mut compiler, &lexer_definition, vec![
+            CharImm { ch: 'a' },
+            Split { then_instr_index: 2, else_instr_index: 5 },
+            CharImm { ch: 'a' },
+            Split { then_instr_index: 4, else_instr_index: 5 },
+            CharImm { ch: 'a' },
+            Match { token_type_index: 2 }
+        ], vec![]);
+    }
+
+    #[test]
+    fn compile_unbounded_repeat() {
+        let mut compiler = Compiler::new();
+        let expr = Expr::Repeat { expr: Box::new(Expr::Single { ch: 'a' }), min: 2, max: None };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
+        check_compiler(&mut compiler, &lexer_definition, vec![
+            CharImm { ch: 'a' },
+            CharImm { ch: 'a' },
+            Split { then_instr_index: 3, else_instr_index: 5 },
+            CharImm { ch: 'a' },
+            Split { then_instr_index: 3, else_instr_index: 5 },
+            Match { token_type_index: 2 }
+        ], vec![]);
+    }
+
+    #[test]
+    fn repeat_limit() {
+        let mut compiler = Compiler::new().with_repetition_limit(10);
+        let expr = Expr::Repeat { expr: Box::new(Expr::Single { ch: 'a' }), min: 2, max: Some(11) };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
+        assert_eq!(Err(CompileError::RepetitionTooLarge { count: 11, limit: 10 }), compiler.generate_lexer(&lexer_definition));
+    }
+
+    #[test]
+    fn nested_repeat_limit() {
+        let repeat = |expr, count| Expr::Repeat { expr: Box::new(expr), min: count, max: Some(count) };
+        let nested = |count| LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", repeat(Expr::Seq { exprs: vec![Expr::Single { ch: 'b' }, repeat(Expr::Single { ch: 'a' }, count)] }, count))
+        ]);
+        let mut compiler = Compiler::new();
+        assert_eq!(Err(CompileError::RepetitionTooLarge { count: 1_000_000, limit: 1000 }), compiler.generate_lexer(&nested(1000)));
+        let mut compiler = Compiler::new().with_repetition_limit(30);
+        assert_eq!(Err(CompileError::RepetitionTooLarge { count: 36, limit: 30 }), compiler.generate_lexer(&nested(6)));
+        let mut compiler = Compiler::new().with_repetition_limit(30);
+        assert_eq!(Ok(()), compiler.generate_lexer(&nested(5)));
+        let vm = compiler.get_vm().unwrap();
+        let text = "baaaaa".repeat(5);
+        assert_eq!(vec![TokenRaw::new(30, 2), TokenRaw::new(0, END_TOKEN_INDEX)], vm.tokenize(&text).collect::<Vec<TokenRaw>>());
+    }
+
+    #[test]
+    fn compile_negated_class() {
+        let mut compiler = Compiler::new();
+        let expr = Expr::Class { ranges: vec![('"', '"'), ('a', 'z')], negated: true };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
+        check_compiler(&mut compiler, &lexer_definition, vec![
+            Invert { count: 2 },
+            CharImm { ch: '"' },
+            RangeImm { from: 'a', to: 'z' },
+            Match { token_type_index: 2 }
+        ], vec![]);
+    }
+
+    #[test]
+    fn compile_any() {
+        let mut compiler = Compiler::new();
+        let expr = Expr::Seq { exprs: vec![
+            Expr::Single { ch: '#' },
+            Expr::Any { matches_newline: false },
+            Expr::Any { matches_newline: true },
+        ]};
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
+        check_compiler(&mut compiler, &lexer_definition, vec![
+            CharImm { ch: '#' },
+            Any { matches_newline: false },
+            Any { matches_newline: true },
+            Match { token_type_index: 2 }
+        ], vec![]);
+    }
+
+    #[test]
+    fn wide_split() {
+        let mut compiler = Compiler::new().with_repetition_limit(10_000);
+        let expr = Expr::Repeat { expr: Box::new(Expr::Single { ch: 'a' }), min: 0, max: Some(10_000) };
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
+        compiler.generate_lexer(&lexer_definition).unwrap();
+        let prog_data = compiler.get_prog_data().unwrap();
+        match decode(prog_data.code[0]) {
+            SplitWide { table_index } => {
+                let table_index = table_index as usize;
+                assert_eq!(&[1, 20_000], &prog_data.constant_pool[table_index..table_index + 2]);
+            }
+            instruction => panic!("Expected split_wide, found {}", instruction)
+        }
+    }
+
+    #[test]
+    fn split_many() {
+        let mut compiler = Compiler::new();
+        let lexer_definition = LexerDefinition::new(vec!['a', 'b', 'c'].into_iter().enumerate()
+            .map(|(index, ch)| TokenDefinition::new(index as u16 + 2, &ch.to_string(), Expr::Single { ch }))
+            .collect());
+        check_compiler(&mut compiler, &lexer_definition, vec![
+            SplitMany { table_index: 0 },
+            CharImm { ch: 'a' },
+            Match { token_type_index: 2 },
+            CharImm { ch: 'b' },
+            Match { token_type_index: 3 },
+            CharImm { ch: 'c' },
+            Match { token_type_index: 4 }
+        ], vec![3, 1, 3, 5]);
+    }
+
+    #[test]
+    fn ranks() {
+        let tokens = || vec![
+            TokenDefinition::new(2, "ident", Expr::Single { ch: 'a' }),
+            TokenDefinition::new(3, "keyword", Expr::Single { ch: 'a' }).with_priority(1),
+            TokenDefinition::new(4, "other", Expr::Single { ch: 'a' }),
+        ];
+        assert_eq!(Vec::<u32>::new(), token_ranks(&LexerDefinition::new(tokens())));
+        assert_eq!(vec![0, 0, 2, 3, 1], token_ranks(&LexerDefinition::new(tokens()).with_tie_break(TieBreak::Priority)));
+        assert_eq!(vec![0, 0, 3, 2, 1], token_ranks(&LexerDefinition::new(tokens()).with_tie_break(TieBreak::FirstDefined)));
+    }
+
+    #[test]
+    fn modes() {
+        let mut compiler = Compiler::new();
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "open", Expr::Single { ch: '<' }).with_action(ModeAction::Push("TAG".to_string())),
+        ]).with_mode(ModeDefinition::new("TAG", vec![
+            TokenDefinition::new(3, "name", Expr::Single { ch: 'a' }),
+            TokenDefinition::new(4, "close", Expr::Single { ch: '>' }).with_action(ModeAction::Pop),
+        ]));
+        check_compiler(&mut compiler, &lexer_definition, vec![
+            OuterLexer { table_index: 0 },
+            CharImm { ch: '<' },
+            Match { token_type_index: 2 },
+            Split { then_instr_index: 4, else_instr_index: 6 },
+            CharImm { ch: 'a' },
+            Match { token_type_index: 3 },
+            CharImm { ch: '>' },
+            Match { token_type_index: 4 }
+        ], vec![2, 1, 3]);
+        compiler.generate_lexer(&lexer_definition).unwrap();
+        let vm = compiler.get_vm().unwrap();
+        assert_eq!(2, vm.mode_count());
+        assert_eq!(TokenAction::Push(1), vm.token_action(0, 2));
+        assert_eq!(TokenAction::Pop, vm.token_action(1, 4));
+        assert_eq!(TokenAction::None, vm.token_action(1, 2));
+    }
+
+    #[test]
+    fn unknown_mode() {
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "open", Expr::Single { ch: '<' }).with_action(ModeAction::Switch("TAG".to_string())),
+        ]);
+        assert_eq!(Err(CompileError::UnknownMode { name: "TAG".to_string() }), Compiler::new().generate_lexer(&lexer_definition));
+        let lexer_definition = LexerDefinition::new(vec![])
+            .with_mode(ModeDefinition::new(INITIAL_MODE_NAME, vec![]));
+        assert_eq!(Err(CompileError::DuplicateMode { name: INITIAL_MODE_NAME.to_string() }), Compiler::new().generate_lexer(&lexer_definition));
+    }
+
+    #[test]
+    fn byte_mode() {
+        let mut compiler = Compiler::new().with_byte_mode(true);
+        let expr = Expr::Seq { exprs: vec![
+            Expr::Single { ch: '\u{E9}' },
+            Expr::Range { from: '\u{80}', to: '\u{7FF}' },
+            Expr::Class { ranges: vec![('a', 'a'), ('\u{3B1}', '\u{3B1}')], negated: false },
+        ]};
+        let lexer_definition = LexerDefinition::new(vec![
+            TokenDefinition::new(2, "foo", expr)
+        ]);
+        check_compiler(&mut compiler, &lexer_definition, vec![
+            CharImm { ch: '\u{C3}' },
+            CharImm { ch: '\u{A9}' },
+            RangeImm { from: '\u{C2}', to: '\u{DF}' },
+            RangeImm { from: '\u{80}', to: '\u{BF}' },
+            SplitMany { table_index: 0 },
+            CharImm { ch: 'a' },
+            Jmp { instr_index: 9 },
+            CharImm { ch: '\u{CE}' },
+            CharImm { ch: '\u{B1}' },
+            Match { token_type_index: 2 }
+        ], vec![2, 5, 7]);
+    }
+
     fn check_compiler(compiler: &mut Compiler, lexer_definition: &LexerDefinition, expected: Vec<Instruction>, pool: Vec<u32>) {
-        compiler.generate_lexer(&lexer_definition);
-        let prog_data = compiler.get_prog_data();
+        compiler.generate_lexer(lexer_definition).unwrap();
+        let prog_data = compiler.get_prog_data().unwrap();
         let instructions: Vec<Instruction> = prog_data.code.iter()
             .map(|instr| decode(*instr))
             .collect();
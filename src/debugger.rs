@@ -0,0 +1,421 @@
+use assembler::CodePointer;
+use ast::Expr;
+use disasm::decode;
+use disasm::Instruction;
+use observer::NoopObserver;
+use vm::Vm;
+use vm::char_at;
+use vm::TokenRaw;
+use vm::ERROR_TOKEN_INDEX;
+use vm::END_TOKEN_INDEX;
+use std::mem::swap;
+use bit_set::BitSet;
+
+/// Code of the single token definition
+#[derive(Debug, Clone)]
+pub struct TokenCode {
+    /// First instruction of the token code
+    pub start: CodePointer,
+    /// Instruction after the Match of the token
+    pub end: CodePointer,
+    pub token_type_index: u16,
+    pub name: String,
+    pub expr: Expr,
+}
+
+/// Maps code back to token definitions, it is produced by `Compiler`
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    tokens: Vec<TokenCode>,
+}
+
+impl DebugInfo {
+    pub fn new(tokens: Vec<TokenCode>) -> Self {
+        DebugInfo { tokens }
+    }
+
+    pub fn tokens(&self) -> &[TokenCode] {
+        &self.tokens
+    }
+
+    /// Token definition, which code contains `code_pointer`, None for the code dispatching to tokens
+    pub fn token_at(&self, code_pointer: CodePointer) -> Option<&TokenCode> {
+        self.tokens.iter().find(|token| token.start <= code_pointer && code_pointer < token.end)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stops, when some thread reaches the instruction
+    CodePointer(CodePointer),
+    /// Stops before the char at the offset is fed to the threads
+    Offset(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// Threads are started for the match attempt at the offset
+    MatchStart { offset: usize },
+    /// Char at the offset is fed to the threads
+    Char { offset: usize, ch: char },
+    /// Token is finished
+    Token(TokenRaw),
+    /// All tokens are produced
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(Breakpoint),
+    Finished,
+}
+
+/// Active thread of the lexer
+#[derive(Debug)]
+pub struct ThreadInfo<'a> {
+    pub code_pointer: CodePointer,
+    pub instruction: Instruction,
+    /// Token definition, which the instruction belongs to
+    pub token: Option<&'a TokenCode>,
+}
+
+/// Runs the lexer one char at a time, producing the same tokens as `LexingSession`
+pub struct Debugger<'a, 'b> {
+    vm: &'a Vm,
+    text: &'b str,
+    /// Start of the current match attempt
+    start: usize,
+    /// Offset of the next char to feed
+    offset: usize,
+    /// Start of the error token, which is not finished yet
+    error_start: Option<usize>,
+    /// Longest match of the current attempt: token type index and end
+    best: Option<(u16, usize)>,
+    /// Token, which is finished, but not reported yet
+    pending: Option<TokenRaw>,
+    is_matching: bool,
+    is_end: bool,
+    current_threads: BitSet,
+    next_threads: BitSet,
+    advanced_threads: Vec<CodePointer>,
+    visited: BitSet,
+    breakpoints: Vec<Breakpoint>,
+    tokens: Vec<TokenRaw>,
+    /// Same as in `LexingSession`
+    mode_stack: Vec<u16>,
+}
+
+impl<'a, 'b> Debugger<'a, 'b> {
+    pub fn new(vm: &'a Vm, text: &'b str) -> Self {
+        let code_len = vm.code().len();
+        Debugger {
+            vm,
+            text,
+            start: 0,
+            offset: 0,
+            error_start: None,
+            best: None,
+            pending: None,
+            is_matching: false,
+            is_end: false,
+            current_threads: BitSet::with_capacity(code_len),
+            next_threads: BitSet::with_capacity(code_len),
+            advanced_threads: Vec::new(),
+            visited: BitSet::with_capacity(code_len),
+            breakpoints: Vec::new(),
+            tokens: Vec::new(),
+            mode_stack: vec![0],
+        }
+    }
+
+    /// Starts a match attempt, feeds one char or finishes one token
+    pub fn step(&mut self) -> DebugEvent {
+        loop {
+            if let Some(token) = self.pending.take() {
+                return self.emit(token);
+            }
+            if self.is_end {
+                return DebugEvent::Finished;
+            }
+            if !self.is_matching {
+                if self.start == self.text.len() {
+                    if let Some(error_start) = self.error_start.take() {
+                        let token = TokenRaw::new((self.start - error_start) as u32, ERROR_TOKEN_INDEX);
+                        return self.emit(token);
+                    }
+                    self.is_end = true;
+                    return self.emit(TokenRaw::new(0, END_TOKEN_INDEX));
+                }
+                self.begin_match();
+                return DebugEvent::MatchStart { offset: self.start };
+            }
+            if let Some((ch, len)) = self.next_char() {
+                return self.feed(ch, len);
+            }
+            self.is_matching = false;
+            match self.best.take() {
+                Some((token_index, end)) => {
+                    let token = TokenRaw::new((end - self.start) as u32, token_index);
+                    let error_start = self.error_start.take();
+                    let error_end = self.start;
+                    self.start = end;
+                    match error_start {
+                        Some(error_start) => {
+                            self.pending = Some(token);
+                            let error = TokenRaw::new((error_end - error_start) as u32, ERROR_TOKEN_INDEX);
+                            return self.emit(error);
+                        }
+                        None => return self.emit(token),
+                    }
+                }
+                None => {
+                    // error token lasts until some token matches
+                    if self.error_start.is_none() {
+                        self.error_start = Some(self.start);
+                    }
+                    self.start = self.char_end(self.start);
+                }
+            }
+        }
+    }
+
+    /// Steps until some breakpoint is hit or all tokens are produced
+    pub fn run(&mut self) -> StopReason {
+        loop {
+            if let DebugEvent::Finished = self.step() {
+                return StopReason::Finished;
+            }
+            if let Some(breakpoint) = self.hit_breakpoint() {
+                return StopReason::Breakpoint(breakpoint);
+            }
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Returns true, if the breakpoint was set
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|existing| *existing != breakpoint);
+        len != self.breakpoints.len()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Offset of the char, which will be fed next
+    pub fn offset(&self) -> usize {
+        if self.is_matching { self.offset } else { self.start }
+    }
+
+    /// Start of the token, which is being matched
+    pub fn token_start(&self) -> usize {
+        self.start
+    }
+
+    /// Longest match of the current attempt: token type index and end offset
+    pub fn best_match(&self) -> Option<(u16, usize)> {
+        self.best
+    }
+
+    /// Tokens, produced so far
+    pub fn tokens(&self) -> &[TokenRaw] {
+        &self.tokens
+    }
+
+    /// Mode, in which the current token is matched
+    pub fn mode(&self) -> u16 {
+        *self.mode_stack.last().unwrap()
+    }
+
+    pub fn vm(&self) -> &'a Vm {
+        self.vm
+    }
+
+    /// Active threads, which are waiting for the next char
+    pub fn threads(&self) -> Vec<ThreadInfo<'a>> {
+        if !self.is_matching {
+            return Vec::new();
+        }
+        let vm = self.vm;
+        self.current_threads.iter()
+            .map(|code_pointer| {
+                let code_pointer = code_pointer as CodePointer;
+                ThreadInfo {
+                    code_pointer,
+                    instruction: decode(vm.code()[code_pointer as usize]),
+                    token: vm.debug_info().token_at(code_pointer),
+                }
+            })
+            .collect()
+    }
+
+    fn hit_breakpoint(&self) -> Option<Breakpoint> {
+        let offset = self.offset();
+        self.breakpoints.iter().cloned().find(|breakpoint| match *breakpoint {
+            Breakpoint::CodePointer(code_pointer) =>
+                self.is_matching && self.current_threads.contains(code_pointer as usize),
+            Breakpoint::Offset(breakpoint_offset) => self.next_char().is_some() && breakpoint_offset == offset,
+        })
+    }
+
+    fn begin_match(&mut self) {
+        self.is_matching = true;
+        self.offset = self.start;
+        self.best = None;
+        self.current_threads.clear();
+        self.visited.clear();
+        self.vm.add_thread(self.vm.mode_entry(self.mode()), &mut self.current_threads, &mut self.visited, &mut NoopObserver);
+    }
+
+    /// Next char for the current match attempt and its length in bytes, None if the attempt is over.
+    /// In byte mode every byte is the char, as in `LexingSession`
+    fn next_char(&self) -> Option<(char, usize)> {
+        if !self.is_matching || self.current_threads.is_empty() {
+            return None;
+        }
+        char_at(self.text.as_bytes(), self.offset, self.vm.is_byte_mode())
+    }
+
+    fn feed(&mut self, ch: char, len: usize) -> DebugEvent {
+        let offset = self.offset;
+        let class = self.vm.classes().class_of(ch);
+        let match_res = self.vm.match_char(class, &self.current_threads, &mut self.next_threads, &mut self.advanced_threads, &mut self.visited, &mut NoopObserver);
+        self.offset += len;
+        if let Some(token_index) = match_res.max_matched_token_index {
+            self.best = Some((token_index, self.offset));
+        }
+        self.current_threads.clear();
+        swap(&mut self.current_threads, &mut self.next_threads);
+        DebugEvent::Char { offset, ch }
+    }
+
+    fn emit(&mut self, token: TokenRaw) -> DebugEvent {
+        self.vm.apply_token_action(&mut self.mode_stack, token.token_type_index());
+        self.tokens.push(token);
+        DebugEvent::Token(token)
+    }
+
+    /// Offset after the char at `offset`
+    fn char_end(&self, offset: usize) -> usize {
+        offset + char_at(self.text.as_bytes(), offset, self.vm.is_byte_mode()).map(|(_, len)| len).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::LexerDefinition;
+    use ast::TokenDefinition;
+    use ast::ModeDefinition;
+    use ast::ModeAction;
+    use ast::INITIAL_MODE_NAME;
+    use compiler::Compiler;
+
+    fn compile(patterns: &[(&str, &str)]) -> Vm {
+        let tokens = patterns.iter().enumerate()
+            .map(|(position, (name, pattern))| TokenDefinition::from_pattern(position as u16 + 2, name, pattern).unwrap())
+            .collect();
+        Compiler::new().compile_lexer(&LexerDefinition::new(tokens)).unwrap()
+    }
+
+    #[test]
+    fn same_tokens_as_session() {
+        let vm = compile(&[("NUM", "[0-9]+"), ("PLUS", "\\+"), ("ARROW", "->-")]);
+        for text in &["12+3", "1?+2", "->-x->", "??", "", "1->2"] {
+            let mut debugger = vm.debug(text);
+            while debugger.step() != DebugEvent::Finished {}
+            let expected: Vec<TokenRaw> = vm.tokenize(text).collect();
+            assert_eq!(&expected[..], debugger.tokens(), "text: {:?}", text);
+        }
+    }
+
+    #[test]
+    fn modes() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "WORD", "[a-z]+").unwrap(),
+            TokenDefinition::from_pattern(3, "HASH", "#").unwrap().with_action(ModeAction::Switch("COMMENT".to_string())),
+        ]).with_mode(ModeDefinition::new("COMMENT", vec![
+            TokenDefinition::from_pattern(4, "COMMENT", "[^\n]+").unwrap(),
+            TokenDefinition::from_pattern(5, "NEWLINE", "\n").unwrap().with_action(ModeAction::Switch(INITIAL_MODE_NAME.to_string())),
+        ]));
+        let vm = Compiler::new().compile_lexer(&definition).unwrap();
+        let text = "ab#cd ef\ngh#";
+        let mut debugger = vm.debug(text);
+        assert_eq!(StopReason::Finished, debugger.run());
+        assert_eq!(1, debugger.mode());
+        let expected: Vec<TokenRaw> = vm.tokenize(text).collect();
+        assert_eq!(&expected[..], debugger.tokens());
+        assert_eq!(vec![2, 3, 4, 5, 2, 3, END_TOKEN_INDEX], expected.iter().map(|token| token.token_type_index()).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn step() {
+        let vm = compile(&[("AB", "ab")]);
+        let mut debugger = vm.debug("ab");
+        assert_eq!(DebugEvent::MatchStart { offset: 0 }, debugger.step());
+        assert_eq!(DebugEvent::Char { offset: 0, ch: 'a' }, debugger.step());
+        let threads = debugger.threads();
+        assert_eq!(1, threads.len());
+        assert_eq!(Instruction::CharImm { ch: 'b' }, threads[0].instruction);
+        assert_eq!("AB", threads[0].token.unwrap().name);
+        assert_eq!(DebugEvent::Char { offset: 1, ch: 'b' }, debugger.step());
+        assert_eq!(Some((2, 2)), debugger.best_match());
+        assert_eq!(DebugEvent::Token(TokenRaw::new(2, 2)), debugger.step());
+        assert_eq!(DebugEvent::Token(TokenRaw::new(0, END_TOKEN_INDEX)), debugger.step());
+        assert_eq!(DebugEvent::Finished, debugger.step());
+    }
+
+    #[test]
+    fn threads_wait_for_char() {
+        // splits, jumps and matches are followed, but they are not threads
+        let vm = compile(&[("A", "a+"), ("BC", "b*c")]);
+        let mut debugger = vm.debug("aa");
+        assert_eq!(DebugEvent::MatchStart { offset: 0 }, debugger.step());
+        let instructions: Vec<Instruction> = debugger.threads().into_iter().map(|thread| thread.instruction).collect();
+        assert_eq!(vec![Instruction::CharImm { ch: 'a' }, Instruction::CharImm { ch: 'b' }, Instruction::CharImm { ch: 'c' }], instructions);
+        assert_eq!(DebugEvent::Char { offset: 0, ch: 'a' }, debugger.step());
+        let instructions: Vec<Instruction> = debugger.threads().into_iter().map(|thread| thread.instruction).collect();
+        assert_eq!(vec![Instruction::CharImm { ch: 'a' }], instructions);
+    }
+
+    #[test]
+    fn byte_mode() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "WORD", "[a-z\u{3b1}-\u{3c9}]+").unwrap(),
+            TokenDefinition::from_pattern(3, "WS", " +").unwrap(),
+        ]);
+        let vm = Compiler::new().with_byte_mode(true).compile_lexer(&definition).unwrap();
+        let text = "a\u{3b1} \u{E9}b";
+        let mut debugger = vm.debug(text);
+        assert_eq!(DebugEvent::MatchStart { offset: 0 }, debugger.step());
+        assert_eq!(DebugEvent::Char { offset: 0, ch: 'a' }, debugger.step());
+        assert_eq!(DebugEvent::Char { offset: 1, ch: '\u{CE}' }, debugger.step());
+        assert_eq!(DebugEvent::Char { offset: 2, ch: '\u{B1}' }, debugger.step());
+        debugger.run();
+        let expected: Vec<TokenRaw> = vm.tokenize_bytes(text.as_bytes()).unwrap().collect();
+        assert_eq!(&expected[..], debugger.tokens());
+    }
+
+    #[test]
+    fn breakpoints() {
+        let vm = compile(&[("A", "a+"), ("B", "b")]);
+        let b_start = vm.debug_info().tokens()[1].start;
+        let mut debugger = vm.debug("aaba");
+        debugger.add_breakpoint(Breakpoint::Offset(2));
+        assert_eq!(StopReason::Breakpoint(Breakpoint::Offset(2)), debugger.run());
+        assert!(debugger.remove_breakpoint(Breakpoint::Offset(2)));
+        // thread of B is started at the beginning of every token
+        debugger.add_breakpoint(Breakpoint::CodePointer(b_start));
+        assert_eq!(StopReason::Breakpoint(Breakpoint::CodePointer(b_start)), debugger.run());
+        assert_eq!(&[TokenRaw::new(2, 2)], debugger.tokens());
+        assert!(debugger.remove_breakpoint(Breakpoint::CodePointer(b_start)));
+        assert_eq!(StopReason::Finished, debugger.run());
+        assert_eq!(4, debugger.tokens().len());
+    }
+}
@@ -0,0 +1,642 @@
+use assembler::CodePointer;
+use assembler::ProgramData;
+use classes::CharClasses;
+use observer::NoopObserver;
+use vm::Vm;
+use vm::TokenRaw;
+use vm::ERROR_TOKEN_INDEX;
+use vm::END_TOKEN_INDEX;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use bit_set::BitSet;
+use observer::VmObserver;
+use table::DfaTable;
+use table::TableError;
+
+pub type StateId = u32;
+
+/// State without threads, every transition from it leads to itself
+pub const DEAD_STATE: StateId = 0;
+/// State with threads of the program start
+pub const START_STATE: StateId = 1;
+/// Default limit for the number of states of the automaton
+pub const DEFAULT_STATE_LIMIT: usize = 10_000;
+/// Transition of `LazyDfa`, which is not computed yet
+const UNKNOWN_STATE: StateId = StateId::MAX;
+/// Cache clear is considered bad, if the cache served less than that number of steps per state since the previous one
+const MIN_STEPS_PER_STATE: usize = 10;
+/// Number of bad clears, after which `LazyDfa` is considered thrashing
+const MAX_BAD_CLEARS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DfaError {
+    /// Subset construction produced more states, than allowed
+    TooManyStates { limit: usize },
+    /// Automaton has no mode stack, so the program must have the initial mode only and no token actions
+    ModesNotSupported,
+    /// Automaton steps over chars, so the program must not be compiled in byte mode
+    ByteModeNotSupported,
+}
+
+impl fmt::Display for DfaError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DfaError::TooManyStates { limit } => write!(f, "automaton has more than {} states", limit),
+            DfaError::ModesNotSupported => write!(f, "lexer modes are not supported by automaton"),
+            DfaError::ByteModeNotSupported => write!(f, "byte mode is not supported by automaton"),
+        }
+    }
+}
+
+impl Error for DfaError {}
+
+/// Deterministic automaton, every state corresponds to a set of Vm threads
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    classes: CharClasses,
+    /// Next state by `state * classes.len() + class`
+    transitions: Vec<StateId>,
+    /// Token type, matched after entering the state
+    accepts: Vec<Option<u16>>,
+}
+
+impl Dfa {
+    pub fn from_vm(vm: &Vm) -> Result<Self, DfaError> {
+        Dfa::build(vm, DEFAULT_STATE_LIMIT)
+    }
+
+    pub fn from_program(program: &ProgramData) -> Result<Self, DfaError> {
+        let vm = Vm::new(program.code.clone(), program.constant_pool.clone())
+            .with_token_ranks(program.token_ranks.clone())
+            .with_token_actions(program.token_actions.clone())
+            .with_byte_mode(program.byte_mode);
+        Dfa::from_vm(&vm)
+    }
+
+    /// Subset construction over Vm threads, fails if the automaton has more than `state_limit` states
+    pub fn build(vm: &Vm, state_limit: usize) -> Result<Self, DfaError> {
+        if vm.mode_count() > 1 || vm.has_token_actions() {
+            return Err(DfaError::ModesNotSupported);
+        }
+        if vm.is_byte_mode() {
+            return Err(DfaError::ByteModeNotSupported);
+        }
+        let classes = vm.classes().clone();
+        let class_count = classes.len();
+        let mut start = BitSet::with_capacity(vm.code().len());
+        let mut visited = BitSet::with_capacity(vm.code().len());
+        let start_accept = vm.add_thread(vm.mode_entry(0), &mut start, &mut visited, &mut NoopObserver);
+        let mut dfa = Dfa { classes, transitions: vec![DEAD_STATE; class_count], accepts: vec![None] };
+        let mut state_ids: HashMap<(BitSet, Option<u16>), StateId> = HashMap::new();
+        state_ids.insert((BitSet::new(), None), DEAD_STATE);
+        let mut states = vec![BitSet::new()];
+        dfa.add_state((start, start_accept), &mut states, &mut state_ids);
+        let mut advanced: Vec<CodePointer> = Vec::new();
+        let mut state = START_STATE as usize;
+        while state < states.len() {
+            for class in 0..class_count {
+                let mut next = BitSet::with_capacity(vm.code().len());
+                let match_res = vm.match_char(class, &states[state], &mut next, &mut advanced, &mut visited, &mut NoopObserver);
+                let key = (next, match_res.max_matched_token_index);
+                let next_id = match state_ids.get(&key) {
+                    Some(&id) => id,
+                    None => {
+                        if states.len() >= state_limit {
+                            return Err(DfaError::TooManyStates { limit: state_limit });
+                        }
+                        dfa.add_state(key, &mut states, &mut state_ids)
+                    }
+                };
+                dfa.transitions[state * class_count + class] = next_id;
+            }
+            state += 1;
+        }
+        Ok(dfa)
+    }
+
+    /// State is identified by its threads and the token type, matched on entering it
+    fn add_state(&mut self, key: (BitSet, Option<u16>), states: &mut Vec<BitSet>, state_ids: &mut HashMap<(BitSet, Option<u16>), StateId>) -> StateId {
+        let id = states.len() as StateId;
+        self.accepts.push(key.1);
+        self.transitions.extend((0..self.classes.len()).map(|_| DEAD_STATE));
+        states.push(key.0.clone());
+        state_ids.insert(key, id);
+        id
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.accepts.len()
+    }
+
+    pub fn classes(&self) -> &CharClasses {
+        &self.classes
+    }
+
+    pub fn next_state(&self, state: StateId, ch: char) -> StateId {
+        self.transitions[state as usize * self.classes.len() + self.classes.class_of(ch)]
+    }
+
+    /// Token type, matched by the text, which leads to the state
+    pub fn accept(&self, state: StateId) -> Option<u16> {
+        self.accepts[state as usize]
+    }
+
+    /// Equivalent automaton with the minimal number of states (Hopcroft's algorithm).
+    /// States with different accepted token types are never merged, dead state stays separate
+    pub fn minimize(&self) -> Dfa {
+        let state_count = self.state_count();
+        let class_count = self.classes.len();
+        // sources of transitions by `target * class_count + class`
+        let mut inverse: Vec<Vec<StateId>> = vec![Vec::new(); state_count * class_count];
+        for state in 0..state_count {
+            for class in 0..class_count {
+                let target = self.transitions[state * class_count + class] as usize;
+                inverse[target * class_count + class].push(state as StateId);
+            }
+        }
+        let mut block_of: Vec<usize> = vec![0; state_count];
+        let mut blocks: Vec<Vec<StateId>> = Vec::new();
+        let mut initial_blocks: HashMap<(bool, Option<u16>), usize> = HashMap::new();
+        for (state, state_block) in block_of.iter_mut().enumerate() {
+            let key = (state == DEAD_STATE as usize, self.accepts[state]);
+            let block = *initial_blocks.entry(key).or_insert_with(|| {
+                blocks.push(Vec::new());
+                blocks.len() - 1
+            });
+            blocks[block].push(state as StateId);
+            *state_block = block;
+        }
+        let mut worklist: Vec<usize> = (0..blocks.len()).collect();
+        let mut in_worklist = vec![true; blocks.len()];
+        let mut is_marked = vec![false; state_count];
+        while let Some(splitter) = worklist.pop() {
+            in_worklist[splitter] = false;
+            let splitter_states = blocks[splitter].clone();
+            for class in 0..class_count {
+                let mut marked: Vec<StateId> = Vec::new();
+                for &target in &splitter_states {
+                    for &source in &inverse[target as usize * class_count + class] {
+                        if !is_marked[source as usize] {
+                            is_marked[source as usize] = true;
+                            marked.push(source);
+                        }
+                    }
+                }
+                let mut touched_blocks: Vec<usize> = marked.iter().map(|&state| block_of[state as usize]).collect();
+                touched_blocks.sort();
+                touched_blocks.dedup();
+                for block in touched_blocks {
+                    let (inside, outside): (Vec<StateId>, Vec<StateId>) = blocks[block].iter()
+                        .partition(|&&state| is_marked[state as usize]);
+                    if outside.is_empty() {
+                        continue;
+                    }
+                    let new_block = blocks.len();
+                    let smaller_is_inside = inside.len() <= outside.len();
+                    for &state in &outside {
+                        block_of[state as usize] = new_block;
+                    }
+                    blocks[block] = inside;
+                    blocks.push(outside);
+                    in_worklist.push(false);
+                    if in_worklist[block] {
+                        worklist.push(new_block);
+                        in_worklist[new_block] = true;
+                    } else {
+                        let smaller = if smaller_is_inside { block } else { new_block };
+                        worklist.push(smaller);
+                        in_worklist[smaller] = true;
+                    }
+                }
+                for state in marked {
+                    is_marked[state as usize] = false;
+                }
+            }
+        }
+        // dead and start states keep their ids
+        let mut new_ids: Vec<Option<StateId>> = vec![None; blocks.len()];
+        let mut representatives: Vec<StateId> = Vec::new();
+        let order = [DEAD_STATE, START_STATE].iter().cloned()
+            .chain(0..state_count as StateId)
+            .filter(|&state| (state as usize) < state_count);
+        for state in order {
+            let block = block_of[state as usize];
+            if new_ids[block].is_none() {
+                new_ids[block] = Some(representatives.len() as StateId);
+                representatives.push(state);
+            }
+        }
+        let mut transitions = Vec::with_capacity(representatives.len() * class_count);
+        for &state in &representatives {
+            for class in 0..class_count {
+                let target = self.transitions[state as usize * class_count + class];
+                transitions.push(new_ids[block_of[target as usize]].unwrap());
+            }
+        }
+        let accepts = representatives.iter().map(|&state| self.accepts[state as usize]).collect();
+        Dfa { classes: self.classes.clone(), transitions, accepts }
+    }
+
+    /// Plain table representation of the automaton
+    pub fn to_table(&self) -> DfaTable {
+        DfaTable {
+            class_starts: self.classes.starts().to_vec(),
+            transitions: self.transitions.clone(),
+            accepts: self.accepts.clone(),
+        }
+    }
+
+    pub fn from_table(table: &DfaTable) -> Result<Self, TableError> {
+        table.validate()?;
+        Ok(Dfa {
+            classes: CharClasses::new(table.class_starts.clone()),
+            transitions: table.transitions.clone(),
+            accepts: table.accepts.clone(),
+        })
+    }
+
+    /// Same as `Vm::tokenize`, but without thread simulation
+    pub fn tokenize<'a, 'b>(&'a self, text: &'b str) -> DfaSession<'a, 'b> {
+        DfaSession { dfa: self, text, position: 0, is_end: false, lookahead: None }
+    }
+
+    /// Longest match at `start`: token type index and end
+    fn longest_match(&self, text: &str, start: usize) -> Option<(u16, usize)> {
+        let mut state = START_STATE;
+        let mut best = None;
+        for (offset, ch) in text[start..].char_indices() {
+            state = self.next_state(state, ch);
+            if state == DEAD_STATE {
+                break;
+            }
+            if let Some(token_index) = self.accept(state) {
+                best = Some((token_index, start + offset + ch.len_utf8()));
+            }
+        }
+        best
+    }
+}
+
+/// DFA states, which are built from Vm threads on demand during lexing.
+/// When the cache is full, it is cleared, frequent clears mean, that simulation of threads is cheaper
+pub struct LazyDfa {
+    class_count: usize,
+    state_limit: usize,
+    threads: Vec<BitSet>,
+    accepts: Vec<Option<u16>>,
+    /// Next state by `state * class_count + class`, `UNKNOWN_STATE` if it is not computed yet
+    transitions: Vec<StateId>,
+    /// State ids by threads and accepted token type
+    state_ids: HashMap<(BitSet, Option<u16>), StateId>,
+    /// Start state by mode
+    starts: Vec<Option<StateId>>,
+    /// Buffer for code pointers of threads, advanced by the current char
+    advanced: Vec<CodePointer>,
+    /// Buffer for instructions, followed while threads are added
+    visited: BitSet,
+    steps_since_clear: usize,
+    bad_clears: usize,
+}
+
+impl LazyDfa {
+    pub fn new(class_count: usize, state_limit: usize) -> Self {
+        let mut dfa = LazyDfa {
+            class_count,
+            state_limit,
+            threads: Vec::new(),
+            accepts: Vec::new(),
+            transitions: Vec::new(),
+            state_ids: HashMap::new(),
+            starts: Vec::new(),
+            advanced: Vec::new(),
+            visited: BitSet::new(),
+            steps_since_clear: 0,
+            bad_clears: 0,
+        };
+        dfa.clear();
+        dfa
+    }
+
+    /// State with threads, started in the mode
+    pub fn start_state<O: VmObserver>(&mut self, vm: &Vm, mode: u16, observer: &mut O) -> StateId {
+        let mode = mode as usize;
+        if let Some(&Some(start)) = self.starts.get(mode) {
+            return start;
+        }
+        let mut threads = BitSet::with_capacity(vm.code().len());
+        self.visited.clear();
+        vm.add_thread(vm.mode_entry(mode as u16), &mut threads, &mut self.visited, observer);
+        let key = (threads, None);
+        let start = match self.state_ids.get(&key) {
+            Some(&start) => start,
+            None => {
+                if self.threads.len() >= self.state_limit {
+                    self.clear();
+                }
+                self.add_state(key.0, key.1)
+            }
+        };
+        if self.starts.len() <= mode {
+            self.starts.resize(mode + 1, None);
+        }
+        self.starts[mode] = Some(start);
+        start
+    }
+
+    /// State after `ch`, previously returned state ids become invalid, if the cache is cleared
+    pub fn next_state<O: VmObserver>(&mut self, vm: &Vm, state: StateId, ch: char, observer: &mut O) -> StateId {
+        self.steps_since_clear += 1;
+        let class = vm.classes().class_of(ch);
+        let transition = state as usize * self.class_count + class;
+        let next = self.transitions[transition];
+        if next != UNKNOWN_STATE {
+            return next;
+        }
+        let mut threads = BitSet::with_capacity(vm.code().len());
+        let match_res = vm.match_char(class, &self.threads[state as usize], &mut threads, &mut self.advanced, &mut self.visited, observer);
+        let key = (threads, match_res.max_matched_token_index);
+        if let Some(&next) = self.state_ids.get(&key) {
+            self.transitions[transition] = next;
+            return next;
+        }
+        if self.threads.len() >= self.state_limit {
+            self.clear();
+            return self.add_state(key.0, key.1);
+        }
+        let next = self.add_state(key.0, key.1);
+        self.transitions[transition] = next;
+        next
+    }
+
+    pub fn threads(&self, state: StateId) -> &BitSet {
+        &self.threads[state as usize]
+    }
+
+    pub fn is_dead(&self, state: StateId) -> bool {
+        state == DEAD_STATE
+    }
+
+    /// Token type, matched by the text, which leads to the state
+    pub fn accept(&self, state: StateId) -> Option<u16> {
+        self.accepts[state as usize]
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// True, if the cache is cleared too often to be useful
+    pub fn is_thrashing(&self) -> bool {
+        self.bad_clears >= MAX_BAD_CLEARS
+    }
+
+    fn clear(&mut self) {
+        if !self.threads.is_empty() && self.steps_since_clear < MIN_STEPS_PER_STATE * self.state_limit {
+            self.bad_clears += 1;
+        }
+        self.steps_since_clear = 0;
+        self.threads.clear();
+        self.accepts.clear();
+        self.transitions.clear();
+        self.state_ids.clear();
+        self.starts.clear();
+        self.add_state(BitSet::new(), None);
+    }
+
+    fn add_state(&mut self, threads: BitSet, accept: Option<u16>) -> StateId {
+        let id = self.threads.len() as StateId;
+        self.accepts.push(accept);
+        // transitions from the dead state lead to itself
+        let unknown = if id == DEAD_STATE { DEAD_STATE } else { UNKNOWN_STATE };
+        self.transitions.extend((0..self.class_count).map(|_| unknown));
+        self.state_ids.insert((threads.clone(), accept), id);
+        self.threads.push(threads);
+        id
+    }
+}
+
+pub struct DfaSession<'a, 'b> {
+    dfa: &'a Dfa,
+    text: &'b str,
+    /// Start of the next token
+    position: usize,
+    is_end: bool,
+    /// Longest match at `position`, if it is already known
+    lookahead: Option<(u16, usize)>,
+}
+
+impl<'a, 'b> DfaSession<'a, 'b> {
+    /// Offset after the char at `offset`
+    fn next_char_end(&self, offset: usize) -> usize {
+        offset + self.text[offset..].chars().next().map(|ch| ch.len_utf8()).unwrap_or(0)
+    }
+}
+
+impl<'a, 'b> Iterator for DfaSession<'a, 'b> {
+    type Item = TokenRaw;
+
+    fn next(&mut self) -> Option<TokenRaw> {
+        if self.is_end {
+            return None;
+        }
+        let start = self.position;
+        if start == self.text.len() {
+            self.is_end = true;
+            return Some(TokenRaw::new(0, END_TOKEN_INDEX));
+        }
+        let best = match self.lookahead.take() {
+            Some(best) => Some(best),
+            None => self.dfa.longest_match(self.text, start),
+        };
+        let token = match best {
+            Some((token_index, end)) => {
+                self.position = end;
+                TokenRaw::new((end - start) as u32, token_index)
+            }
+            None => {
+                // error token lasts until some token matches
+                let mut end = self.next_char_end(start);
+                while end < self.text.len() {
+                    if let Some(best) = self.dfa.longest_match(self.text, end) {
+                        self.lookahead = Some(best);
+                        break;
+                    }
+                    end = self.next_char_end(end);
+                }
+                self.position = end;
+                TokenRaw::new((end - start) as u32, ERROR_TOKEN_INDEX)
+            }
+        };
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::LexerDefinition;
+    use ast::TokenDefinition;
+    use ast::ModeDefinition;
+    use ast::ModeAction;
+    use compiler::Compiler;
+    use vm::TokenAction;
+
+    fn compile(patterns: &[(&str, &str)]) -> Vm {
+        let tokens = patterns.iter().enumerate()
+            .map(|(position, (name, pattern))| TokenDefinition::from_pattern(position as u16 + 2, name, pattern).unwrap())
+            .collect();
+        Compiler::new().compile_lexer(&LexerDefinition::new(tokens)).unwrap()
+    }
+
+    fn check_same_tokens(vm: &Vm, texts: &[&str]) {
+        let dfa = Dfa::from_vm(vm).unwrap();
+        for text in texts {
+            let expected: Vec<TokenRaw> = vm.tokenize(text).collect();
+            let actual: Vec<TokenRaw> = dfa.tokenize(text).collect();
+            assert_eq!(expected, actual, "text: {:?}", text);
+        }
+    }
+
+    #[test]
+    fn same_tokens_as_vm() {
+        let vm = compile(&[
+            ("IF", "if"),
+            ("IDENT", "[a-z_][a-z_0-9]*"),
+            ("NUM", "[0-9]+(\\.[0-9]+)?"),
+            ("STRING", "\"[^\"\\n]*\""),
+            ("COMMENT", "//.*"),
+            ("WS", "\\s+"),
+            ("GREEK", "[\u{3b1}-\u{3c9}]+"),
+        ]);
+        check_same_tokens(&vm, &[
+            "if iff x_1 = 12.5;",
+            "\"str\" \"unclosed\n// comment\nif",
+            "1.x ..",
+            "\u{3b1}\u{3b2} \u{1F600}",
+            "",
+        ]);
+    }
+
+    #[test]
+    fn from_program() {
+        let tokens = vec![TokenDefinition::from_pattern(2, "AB", "a*b").unwrap()];
+        let mut compiler = Compiler::new();
+        compiler.generate_lexer(&LexerDefinition::new(tokens)).unwrap();
+        let dfa = Dfa::from_program(&compiler.get_prog_data().unwrap()).unwrap();
+        // dead, start or after a, after b
+        assert_eq!(3, dfa.state_count());
+        let tokens: Vec<TokenRaw> = dfa.tokenize("aabcb").collect();
+        assert_eq!(vec![
+            TokenRaw::new(3, 2),
+            TokenRaw::new(1, ERROR_TOKEN_INDEX),
+            TokenRaw::new(1, 2),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ], tokens);
+    }
+
+    #[test]
+    fn minimize() {
+        // both variants of the token lead to the same states
+        let vm = compile(&[("AB", "ab|cb"), ("X", "x+")]);
+        let dfa = Dfa::from_vm(&vm).unwrap();
+        let minimal = dfa.minimize();
+        // dead, start, after a, after c, after ab or cb, after x
+        assert_eq!(6, dfa.state_count());
+        // dead, start, after a or c, after ab or cb, after x
+        assert_eq!(5, minimal.state_count());
+        assert_eq!(DEAD_STATE, minimal.next_state(START_STATE, 'b'));
+        for text in &["abcbxxx", "acb", "xbx"] {
+            let expected: Vec<TokenRaw> = vm.tokenize(text).collect();
+            let actual: Vec<TokenRaw> = minimal.tokenize(text).collect();
+            assert_eq!(expected, actual, "text: {:?}", text);
+        }
+    }
+
+    #[test]
+    fn table_round_trip() {
+        let vm = compile(&[("NUM", "[0-9]+"), ("IDENT", "[a-z]+")]);
+        let dfa = Dfa::from_vm(&vm).unwrap().minimize();
+        let restored = Dfa::from_table(&dfa.to_table()).unwrap();
+        let expected: Vec<TokenRaw> = dfa.tokenize("abc 123").collect();
+        let actual: Vec<TokenRaw> = restored.tokenize("abc 123").collect();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn lazy_dfa() {
+        let vm = compile(&[("A", "a+"), ("B", "b")]);
+        let mut dfa = LazyDfa::new(vm.classes().len(), 10);
+        let start = dfa.start_state(&vm, 0, &mut NoopObserver);
+        let after_a = dfa.next_state(&vm, start, 'a', &mut NoopObserver);
+        assert_eq!(Some(2), dfa.accept(after_a));
+        let after_aa = dfa.next_state(&vm, after_a, 'a', &mut NoopObserver);
+        assert_eq!(after_aa, dfa.next_state(&vm, after_aa, 'a', &mut NoopObserver));
+        let after_c = dfa.next_state(&vm, after_a, 'c', &mut NoopObserver);
+        assert!(dfa.is_dead(after_c));
+        // dead, start, after a, states are identified by threads, which wait for a char, and accepted token
+        assert_eq!(3, dfa.state_count());
+    }
+
+    #[test]
+    fn lazy_dfa_start_reuses_state() {
+        let vm = compile(&[("C", "(ab)*c")]);
+        let mut dfa = LazyDfa::new(vm.classes().len(), 3);
+        let start = dfa.start_state(&vm, 0, &mut NoopObserver);
+        dfa.next_state(&vm, start, 'c', &mut NoopObserver);
+        // the cache is full, so it is cleared together with the start state
+        let after_a = dfa.next_state(&vm, start, 'a', &mut NoopObserver);
+        let after_ab = dfa.next_state(&vm, after_a, 'b', &mut NoopObserver);
+        assert_eq!(3, dfa.state_count());
+        // state after ab has the same threads as the start state
+        assert_eq!(after_ab, dfa.start_state(&vm, 0, &mut NoopObserver));
+        assert_eq!(3, dfa.state_count());
+    }
+
+    #[test]
+    fn lazy_dfa_thrashing() {
+        let vm = compile(&[("A", "a+"), ("B", "b")]);
+        let mut dfa = LazyDfa::new(vm.classes().len(), 2);
+        let start = dfa.start_state(&vm, 0, &mut NoopObserver);
+        dfa.next_state(&vm, start, 'a', &mut NoopObserver);
+        assert!(!dfa.is_thrashing());
+        for _ in 0..MAX_BAD_CLEARS {
+            let start = dfa.start_state(&vm, 0, &mut NoopObserver);
+            dfa.next_state(&vm, start, 'a', &mut NoopObserver);
+        }
+        assert!(dfa.is_thrashing());
+    }
+
+    #[test]
+    fn modes_not_supported() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "OPEN", "\"").unwrap().with_action(ModeAction::Push("STRING".to_string())),
+        ]).with_mode(ModeDefinition::new("STRING", vec![
+            TokenDefinition::from_pattern(3, "CLOSE", "\"").unwrap().with_action(ModeAction::Pop),
+        ]));
+        let mut compiler = Compiler::new();
+        compiler.generate_lexer(&definition).unwrap();
+        let program = compiler.get_prog_data().unwrap();
+        assert_eq!(Err(DfaError::ModesNotSupported), Dfa::from_program(&program).map(|_| ()));
+        let mut single_mode = ProgramData::new(program.code.clone(), program.constant_pool.clone());
+        single_mode.token_actions = vec![vec![TokenAction::None, TokenAction::None, TokenAction::Push(0)]];
+        assert_eq!(Err(DfaError::ModesNotSupported), Dfa::from_program(&single_mode).map(|_| ()));
+    }
+
+    #[test]
+    fn byte_mode_not_supported() {
+        let definition = LexerDefinition::new(vec![TokenDefinition::from_pattern(2, "A", "a+").unwrap()]);
+        let mut compiler = Compiler::new().with_byte_mode(true);
+        compiler.generate_lexer(&definition).unwrap();
+        let program = compiler.get_prog_data().unwrap();
+        assert_eq!(Err(DfaError::ByteModeNotSupported), Dfa::from_program(&program).map(|_| ()));
+    }
+
+    #[test]
+    fn state_limit() {
+        let vm = compile(&[("A", "(a|b)*a(a|b)(a|b)(a|b)")]);
+        assert_eq!(Err(DfaError::TooManyStates { limit: 4 }), Dfa::build(&vm, 4).map(|_| ()));
+        check_same_tokens(&vm, &["abab", "aaaa bbbb"]);
+    }
+}
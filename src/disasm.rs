@@ -1,4 +1,3 @@
-use assembler::ProgramData;
 use opcodes::Opcode;
 use assembler::PoolIndex;
 use assembler::CodePointer;
@@ -20,26 +19,35 @@ pub fn decode(code: u32) -> Instruction {
         Opcode::RangeImm => {
             let (left, right) = decode_binary(payload);
             Instruction::RangeImm {
-                from: from_u32(left as u32).unwrap(),
-                to: from_u32(right as u32).unwrap()
+                from: from_u32(left).unwrap(),
+                to: from_u32(right).unwrap()
             }
         },
+        Opcode::Range => {
+            let (left, right) = decode_binary(payload);
+            Instruction::Range { from_index: left, to_index: right }
+        },
         Opcode::Jmp => Instruction::Jmp { instr_index: payload as CodePointer },
-        Opcode::SplitMany => Instruction::SplitMany  { table_index: payload as u16 },
-        _ => unimplemented!("code not implemented yet")
+        Opcode::SplitMany => Instruction::SplitMany  { table_index: payload as PoolIndex },
+        Opcode::SplitWide => Instruction::SplitWide { table_index: payload as PoolIndex },
+        Opcode::RangeWide => Instruction::RangeWide { table_index: payload as PoolIndex },
+        Opcode::Noop => Instruction::Noop,
+        Opcode::Invert => Instruction::Invert { count: payload as u16 },
+        Opcode::Any => Instruction::Any { matches_newline: payload & 1 != 0 },
+        Opcode::OuterLexer => Instruction::OuterLexer { table_index: payload as PoolIndex },
     }
 }
 
-fn decode_binary(payload: u32) -> (u16, u16) {
+fn decode_binary(payload: u32) -> (u32, u32) {
     let mask = 0b0011_1111_1111_1111;
     let left = (payload >> 14) & mask;
     let right = payload & mask;
-    (left as u16, right as u16)
+    (left, right)
 }
 
 fn trim_tag(tagged: u32) -> u32 {
     let mask = !(0b1111 << 28);
-    return tagged & mask;
+    tagged & mask
 }
 
 
@@ -50,9 +58,22 @@ pub enum Instruction {
     /// token_type_index is 
     Match { token_type_index: u16 },
     Split { then_instr_index: CodePointer, else_instr_index: CodePointer },
-    SplitMany { table_index: u16 },
+    /// Table in constant pool contains its length followed by targets
+    SplitMany { table_index: PoolIndex },
+    /// Targets are stored in constant pool at `table_index` and `table_index + 1`
+    SplitWide { table_index: PoolIndex },
     Jmp { instr_index: CodePointer },
     RangeImm { from: char, to: char },
+    /// Range with bounds stored in constant pool
+    Range { from_index: PoolIndex, to_index: PoolIndex },
+    /// Bounds are stored in constant pool at `table_index` and `table_index + 1`
+    RangeWide { table_index: PoolIndex },
+    /// Following `count` instructions are members of negated class
+    Invert { count: u16 },
+    Any { matches_newline: bool },
+    Noop,
+    /// Entry points of lexer modes, table in constant pool contains its length followed by entries
+    OuterLexer { table_index: PoolIndex },
 }
 
 impl fmt::Display for Instruction {
@@ -65,7 +86,14 @@ impl fmt::Display for Instruction {
                 { write!(f, "split then_instr_index: {} else_instr_index: {}, ", then_instr_index, else_instr_index) }
             Instruction::Jmp { instr_index } => { write!(f, "jmp instr_index: {}", instr_index) }
             Instruction::SplitMany { table_index } => { write!(f, "split_many table_index: {}", table_index) }
+            Instruction::SplitWide { table_index } => { write!(f, "split_wide table_index: {}", table_index) }
+            Instruction::RangeWide { table_index } => { write!(f, "range_wide table_index: {}", table_index) }
             Instruction::RangeImm { from, to } => { write!(f, "range_imm from: {}, to: {}", from, to) }
+            Instruction::Range { from_index, to_index } => { write!(f, "range from_index: {}, to_index: {}", from_index, to_index) }
+            Instruction::Invert { count } => { write!(f, "invert count: {}", count) }
+            Instruction::Any { matches_newline } => { write!(f, "any matches_newline: {}", matches_newline) }
+            Instruction::Noop => { write!(f, "noop") }
+            Instruction::OuterLexer { table_index } => { write!(f, "outer_lexer table_index: {}", table_index) }
         }
     }
 }
\ No newline at end of file
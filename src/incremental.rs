@@ -0,0 +1,293 @@
+use restartable::LexerState;
+use vm::LexingSession;
+use vm::TokenRaw;
+use vm::Vm;
+use std::cmp::max;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// Replacement of `start..old_end` range of the text with new text, which ends at `new_end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+impl TextEdit {
+    pub fn new(start: usize, old_end: usize, new_end: usize) -> Self {
+        TextEdit { start, old_end, new_end }
+    }
+
+    /// Edit, which replaces `start..old_end` with `new_text`
+    pub fn replace(start: usize, old_end: usize, new_text: &str) -> Self {
+        TextEdit::new(start, old_end, start + new_text.len())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditError {
+    /// Range `start..old_end` is reversed or is not inside of the old text
+    InvalidOldRange { edit: TextEdit, old_len: usize },
+    /// Range `start..new_end` is reversed or is not inside of the new text
+    InvalidNewRange { edit: TextEdit, new_len: usize },
+    /// Length of the new text differs from the length of the old text with the edit applied
+    LengthMismatch { edit: TextEdit, expected: usize, actual: usize },
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            EditError::InvalidOldRange { edit, old_len } =>
+                write!(f, "invalid edit {}..{} of the old text of length {}", edit.start, edit.old_end, old_len),
+            EditError::InvalidNewRange { edit, new_len } =>
+                write!(f, "invalid edit {}..{} of the new text of length {}", edit.start, edit.new_end, new_len),
+            EditError::LengthMismatch { expected, actual, .. } =>
+                write!(f, "new text length is {}, but the edit gives {}", actual, expected),
+        }
+    }
+}
+
+impl Error for EditError {}
+
+/// Tokens `start..start + removed` are replaced with tokens `start..start + inserted`, indices are in the token list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenChange {
+    pub start: usize,
+    pub removed: usize,
+    pub inserted: usize,
+}
+
+/// Token with the state and the length of the text, which was examined to produce it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexedToken {
+    start: usize,
+    token: TokenRaw,
+    /// State before the token
+    state: LexerState,
+    /// See `LexingSession::scan_end`, relative to the token start
+    scan_length: usize,
+}
+
+impl LexedToken {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.start + self.token.length() as usize
+    }
+
+    pub fn token(&self) -> TokenRaw {
+        self.token
+    }
+
+    pub fn token_type_index(&self) -> u16 {
+        self.token.token_type_index()
+    }
+
+    /// State, with which lexing restarted at the token start produces the same tokens
+    pub fn state(&self) -> LexerState {
+        self.state
+    }
+
+    /// Token may change only after edits before this offset
+    fn scan_end(&self) -> usize {
+        self.start + self.scan_length
+    }
+}
+
+/// Token list of the text, which is updated after edits by relexing of the affected tokens only.
+/// Relexing starts at the first token, which examined edited text, and stops,
+/// when the lexer comes to the start of some old token after the edit in the same state
+pub struct IncrementalLexer<'a> {
+    vm: &'a Vm,
+    /// Tokens including the last one of `END_TOKEN_INDEX` type
+    tokens: Vec<LexedToken>,
+    /// Maximal distance from the token end to its scan end, it never decreases
+    max_lookahead: usize,
+}
+
+impl<'a> IncrementalLexer<'a> {
+    pub fn new(vm: &'a Vm, text: &str) -> Self {
+        let mut lexer = IncrementalLexer { vm, tokens: Vec::new(), max_lookahead: 0 };
+        lexer.relex(text, TextEdit::new(0, 0, text.len()));
+        lexer
+    }
+
+    pub fn tokens(&self) -> &[LexedToken] {
+        &self.tokens
+    }
+
+    /// Mode stack of the token state, the current mode is the last one, None for an unknown state
+    pub fn mode_stack(&self, state: LexerState) -> Option<Vec<u16>> {
+        self.vm.state_mode_stack(state)
+    }
+
+    /// Updates tokens after the edit, `text` is the whole text after the edit.
+    /// Fails without changing tokens, if the edit does not fit the old or the new text
+    pub fn edit(&mut self, text: &str, edit: TextEdit) -> Result<TokenChange, EditError> {
+        // the last token is END at the end of the old text
+        let old_len = self.tokens.last().map(|token| token.start).unwrap_or(0);
+        if edit.start > edit.old_end || edit.old_end > old_len {
+            return Err(EditError::InvalidOldRange { edit, old_len });
+        }
+        if edit.start > edit.new_end || edit.new_end > text.len() {
+            return Err(EditError::InvalidNewRange { edit, new_len: text.len() });
+        }
+        let expected = old_len - edit.old_end + edit.new_end;
+        if expected != text.len() {
+            return Err(EditError::LengthMismatch { edit, expected, actual: text.len() });
+        }
+        Ok(self.relex(text, edit))
+    }
+
+    /// Updates tokens after the valid edit
+    fn relex(&mut self, text: &str, edit: TextEdit) -> TokenChange {
+        let first = self.first_affected(edit.start);
+        let (restart_position, restart_state) = match self.tokens.get(first) {
+            Some(token) => (token.start, token.state),
+            None => (0, LexerState::INITIAL),
+        };
+        let mut session = LexingSession::new(self.vm, text);
+        // token states are interned by the program
+        session.restart(text, restart_position, &self.vm.state_mode_stack(restart_state).unwrap());
+        let mut new_tokens = Vec::new();
+        let mut old_index = first;
+        loop {
+            let position = session.position();
+            let state = self.vm.lexer_state(session.mode_stack());
+            if position >= edit.new_end {
+                let old_position = position - edit.new_end + edit.old_end;
+                while old_index < self.tokens.len() && self.tokens[old_index].start < old_position {
+                    old_index += 1;
+                }
+                if self.tokens.get(old_index).is_some_and(|token| token.start == old_position && token.state == state) {
+                    break;
+                }
+            }
+            let token = match session.next() {
+                Some(token) => token,
+                None => {
+                    old_index = self.tokens.len();
+                    break;
+                }
+            };
+            let token = LexedToken { start: position, token, state, scan_length: session.scan_end() - position };
+            self.max_lookahead = max(self.max_lookahead, token.scan_end() - token.end());
+            new_tokens.push(token);
+        }
+        let change = TokenChange { start: first, removed: old_index - first, inserted: new_tokens.len() };
+        self.tokens.splice(first..old_index, new_tokens);
+        for token in &mut self.tokens[first + change.inserted..] {
+            token.start = token.start - edit.old_end + edit.new_end;
+        }
+        change
+    }
+
+    /// Index of the first token, which examined text at `offset` or after it
+    fn first_affected(&self, offset: usize) -> usize {
+        // scan of the token, which ends before `from`, can't reach the offset
+        let from = offset.saturating_sub(self.max_lookahead);
+        let mut index = self.tokens.partition_point(|token| token.end() < from);
+        while index < self.tokens.len() && self.tokens[index].scan_end() <= offset {
+            index += 1;
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::LexerDefinition;
+    use ast::TokenDefinition;
+    use ast::ModeDefinition;
+    use ast::ModeAction;
+    use compiler::Compiler;
+
+    /// Words, numbers, `->` and nested block comments, where `/*` pushes comment mode and `*/` pops it
+    fn compile() -> Vm {
+        let push = || ModeAction::Push("COMMENT".to_string());
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "WORD", "[a-z]+").unwrap(),
+            TokenDefinition::from_pattern(3, "WS", " +").unwrap(),
+            TokenDefinition::from_pattern(4, "OPEN", r"/\*").unwrap().with_action(push()),
+            TokenDefinition::from_pattern(5, "ARROW", "->").unwrap(),
+            TokenDefinition::from_pattern(6, "NUMBER", r"[0-9]+(\.[0-9]+)?").unwrap(),
+        ]).with_mode(ModeDefinition::new("COMMENT", vec![
+            TokenDefinition::from_pattern(7, "TEXT", r"[^/*]+|/|\*").unwrap(),
+            TokenDefinition::from_pattern(4, "OPEN", r"/\*").unwrap().with_action(push()),
+            TokenDefinition::from_pattern(8, "CLOSE", r"\*/").unwrap().with_action(ModeAction::Pop),
+        ]));
+        Compiler::new().compile_lexer(&definition).unwrap()
+    }
+
+    /// Tokens with mode stacks instead of states, which differ between lexers
+    fn snapshot(lexer: &IncrementalLexer) -> Vec<(usize, TokenRaw, Vec<u16>, usize)> {
+        lexer.tokens().iter()
+            .map(|token| (token.start, token.token, lexer.mode_stack(token.state).unwrap(), token.scan_length))
+            .collect()
+    }
+
+    /// Applies edits one by one, checking tokens against lexing from scratch, returns the last change
+    fn check_edits(initial: &str, edits: &[(usize, usize, &str)]) -> TokenChange {
+        let vm = compile();
+        let mut text = initial.to_string();
+        let mut lexer = IncrementalLexer::new(&vm, &text);
+        let mut change = None;
+        for &(start, old_end, new_text) in edits {
+            text.replace_range(start..old_end, new_text);
+            change = Some(lexer.edit(&text, TextEdit::replace(start, old_end, new_text)).unwrap());
+            assert_eq!(snapshot(&IncrementalLexer::new(&vm, &text)), snapshot(&lexer), "text: {:?}", text);
+        }
+        change.unwrap()
+    }
+
+    #[test]
+    fn edit_inside_token() {
+        let change = check_edits("abc def ghi", &[(5, 6, "x")]);
+        assert_eq!(TokenChange { start: 2, removed: 1, inserted: 1 }, change);
+    }
+
+    #[test]
+    fn edit_joins_tokens() {
+        let change = check_edits("ab - > cd", &[(4, 5, "")]);
+        assert_eq!(TokenChange { start: 2, removed: 3, inserted: 1 }, change);
+        // `1.` is scanned up to the char after the dot, so insertion after it relexes the number
+        let change = check_edits("1. x", &[(2, 2, "5")]);
+        assert_eq!(TokenChange { start: 0, removed: 2, inserted: 1 }, change);
+    }
+
+    #[test]
+    fn edit_changes_mode() {
+        let change = check_edits("a b c d", &[(2, 2, "/*")]);
+        assert_eq!(TokenChange { start: 1, removed: 7, inserted: 4 }, change);
+        let change = check_edits("a /* b */ c /* d */ e", &[(7, 9, ""), (7, 7, "*/")]);
+        assert_eq!(TokenChange { start: 3, removed: 6, inserted: 11 }, change);
+    }
+
+    #[test]
+    fn edits_at_ends() {
+        check_edits("ab ?? cd", &[(0, 0, "x"), (9, 9, "1"), (9, 10, ""), (0, 9, ""), (0, 0, "/* a")]);
+    }
+
+    #[test]
+    fn invalid_edits() {
+        let vm = compile();
+        let mut lexer = IncrementalLexer::new(&vm, "ab cd");
+        let tokens = lexer.tokens().to_vec();
+        let edit = TextEdit::new(3, 6, 3);
+        assert_eq!(Err(EditError::InvalidOldRange { edit, old_len: 5 }), lexer.edit("ab ", edit));
+        let edit = TextEdit::new(3, 2, 3);
+        assert_eq!(Err(EditError::InvalidOldRange { edit, old_len: 5 }), lexer.edit("ab cd", edit));
+        let edit = TextEdit::new(3, 3, 2);
+        assert_eq!(Err(EditError::InvalidNewRange { edit, new_len: 5 }), lexer.edit("ab cd", edit));
+        let edit = TextEdit::new(3, 3, 6);
+        assert_eq!(Err(EditError::InvalidNewRange { edit, new_len: 5 }), lexer.edit("ab cd", edit));
+        let edit = TextEdit::new(0, 5, 1);
+        assert_eq!(Err(EditError::LengthMismatch { edit, expected: 1, actual: 5 }), lexer.edit("ab cd", edit));
+        assert_eq!(&tokens[..], lexer.tokens());
+    }
+}
@@ -0,0 +1,228 @@
+use ast::LexerDefinition;
+use compiler::CompileError;
+use compiler::Compiler;
+use vm::LexingSession;
+use vm::Vm;
+use vm::TokenRaw;
+use vm::ERROR_TOKEN_INDEX;
+use vm::END_TOKEN_INDEX;
+
+/// Name of the token type with `ERROR_TOKEN_INDEX`
+pub const ERROR_TOKEN_NAME: &str = "ERROR";
+/// Name of the token type with `END_TOKEN_INDEX`
+pub const END_TOKEN_NAME: &str = "END";
+
+/// Lexer, compiled from `LexerDefinition`, entry point for tokenization
+pub struct Lexer {
+    vm: Vm,
+    /// Token type names by token type index
+    names: Vec<Option<String>>,
+}
+
+impl Lexer {
+    pub fn new(definition: &LexerDefinition) -> Result<Self, CompileError> {
+        let vm = Compiler::new().compile_lexer(definition)?;
+        let mut names = vec![None; 2];
+        names[ERROR_TOKEN_INDEX as usize] = Some(ERROR_TOKEN_NAME.to_string());
+        names[END_TOKEN_INDEX as usize] = Some(END_TOKEN_NAME.to_string());
+        for token in definition.all_tokens() {
+            let index = token.index as usize;
+            if names.len() <= index {
+                names.resize(index + 1, None);
+            }
+            names[index] = Some(token.name.clone());
+        }
+        Ok(Lexer { vm, names })
+    }
+
+    /// Name of the token type from `TokenDefinition`
+    pub fn token_name(&self, token_type_index: u16) -> Option<&str> {
+        self.names.get(token_type_index as usize)
+            .and_then(|name| name.as_ref())
+            .map(|name| name.as_str())
+    }
+
+    /// Like `tokenize`, but produces tokens with offsets, text and name
+    pub fn tokens<'a, 'b>(&'a self, text: &'b str) -> Tokens<'a, 'b> {
+        Tokens { lexer: self, session: self.tokenize(text), text, offset: 0 }
+    }
+
+    /// Lazily splits text into tokens, finishing with zero length token of `END_TOKEN_INDEX` type.
+    /// Text, which doesn't match any token, is reported as tokens of `ERROR_TOKEN_INDEX` type
+    pub fn tokenize<'a, 'b>(&'a self, text: &'b str) -> LexingSession<'a, 'b> {
+        self.vm.tokenize(text)
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+}
+
+/// Token with its position in the text and resolved type name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a, 'b> {
+    start: usize,
+    end: usize,
+    text: &'b str,
+    token_type_index: u16,
+    name: &'a str,
+}
+
+impl<'a, 'b> Token<'a, 'b> {
+    /// Byte offset of the token start
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset after the token end
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn text(&self) -> &'b str {
+        self.text
+    }
+
+    pub fn token_type_index(&self) -> u16 {
+        self.token_type_index
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+}
+
+pub struct Tokens<'a, 'b> {
+    lexer: &'a Lexer,
+    session: LexingSession<'a, 'b>,
+    text: &'b str,
+    offset: usize,
+}
+
+impl<'a, 'b> Iterator for Tokens<'a, 'b> {
+    type Item = Token<'a, 'b>;
+
+    fn next(&mut self) -> Option<Token<'a, 'b>> {
+        let raw: TokenRaw = self.session.next()?;
+        let start = self.offset;
+        let end = start + raw.length() as usize;
+        self.offset = end;
+        Some(Token {
+            start,
+            end,
+            text: &self.text[start..end],
+            token_type_index: raw.token_type_index(),
+            name: self.lexer.token_name(raw.token_type_index()).unwrap_or(""),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::TokenDefinition;
+    use ast::TieBreak;
+    use ast::ModeDefinition;
+    use ast::ModeAction;
+    use ast::INITIAL_MODE_NAME;
+    use vm::TokenRaw;
+    use vm::END_TOKEN_INDEX;
+
+    #[test]
+    fn tokenize() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "LPAR", r"\(").unwrap(),
+            TokenDefinition::from_pattern(3, "RPAR", r"\)").unwrap(),
+            TokenDefinition::from_pattern(4, "DIGIT", r"\d").unwrap(),
+            TokenDefinition::from_pattern(5, "OP", r"[+\-*/]").unwrap(),
+        ]);
+        let lexer = Lexer::new(&definition).unwrap();
+        let tokens: Vec<TokenRaw> = lexer.tokenize("(1+2)").collect();
+        assert_eq!(vec![
+            TokenRaw::new(1, 2),
+            TokenRaw::new(1, 4),
+            TokenRaw::new(1, 5),
+            TokenRaw::new(1, 4),
+            TokenRaw::new(1, 3),
+            TokenRaw::new(0, END_TOKEN_INDEX),
+        ], tokens);
+    }
+
+    #[test]
+    fn maximal_munch() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "IDENT", "[a-z]+").unwrap(),
+            TokenDefinition::from_pattern(3, "WS", " +").unwrap(),
+            TokenDefinition::from_pattern(4, "ARROW", "->").unwrap(),
+            TokenDefinition::from_pattern(5, "MINUS", "-").unwrap(),
+        ]);
+        let lexer = Lexer::new(&definition).unwrap();
+        let tokens: Vec<&str> = lexer.tokens("let  x->y-").map(|token| token.text()).collect();
+        assert_eq!(vec!["let", "  ", "x", "->", "y", "-", ""], tokens);
+    }
+
+    #[test]
+    fn keyword_priority() {
+        let tokens = || vec![
+            TokenDefinition::from_pattern(2, "IDENT", "[a-z]+").unwrap(),
+            TokenDefinition::from_pattern(3, "IF", "if").unwrap().with_priority(1),
+            TokenDefinition::from_pattern(4, "WS", " ").unwrap(),
+        ];
+        let names = |lexer: &Lexer| -> Vec<String> {
+            lexer.tokens("if iff").map(|token| token.name().to_string()).collect()
+        };
+        let lexer = Lexer::new(&LexerDefinition::new(tokens())).unwrap();
+        assert_eq!(vec!["IF", "WS", "IDENT", "END"], names(&lexer));
+        let lexer = Lexer::new(&LexerDefinition::new(tokens()).with_tie_break(TieBreak::Priority)).unwrap();
+        assert_eq!(vec!["IF", "WS", "IDENT", "END"], names(&lexer));
+        let lexer = Lexer::new(&LexerDefinition::new(tokens()).with_tie_break(TieBreak::FirstDefined)).unwrap();
+        assert_eq!(vec!["IDENT", "WS", "IDENT", "END"], names(&lexer));
+    }
+
+    #[test]
+    fn string_interpolation_modes() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "IDENT", "[a-z]+").unwrap(),
+            TokenDefinition::from_pattern(3, "QUOTE", "\"").unwrap().with_action(ModeAction::Push("STRING".to_string())),
+            TokenDefinition::from_pattern(4, "RBRACE", r"\}").unwrap().with_action(ModeAction::Pop),
+            TokenDefinition::from_pattern(5, "WS", " ").unwrap(),
+        ]).with_mode(ModeDefinition::new("STRING", vec![
+            TokenDefinition::from_pattern(6, "TEXT", "[^\"$]+").unwrap(),
+            TokenDefinition::from_pattern(3, "QUOTE", "\"").unwrap().with_action(ModeAction::Pop),
+            TokenDefinition::from_pattern(7, "INTERP", r"\$\{").unwrap().with_action(ModeAction::Push(INITIAL_MODE_NAME.to_string())),
+        ]));
+        let lexer = Lexer::new(&definition).unwrap();
+        let text = "a \"x ${b \"y\"}z\" c";
+        let tokens: Vec<(&str, &str)> = lexer.tokens(text).map(|token| (token.name(), token.text())).collect();
+        assert_eq!(vec![
+            ("IDENT", "a"), ("WS", " "), ("QUOTE", "\""), ("TEXT", "x "), ("INTERP", "${"),
+            ("IDENT", "b"), ("WS", " "), ("QUOTE", "\""), ("TEXT", "y"), ("QUOTE", "\""),
+            ("RBRACE", "}"), ("TEXT", "z"), ("QUOTE", "\""), ("WS", " "), ("IDENT", "c"), ("END", ""),
+        ], tokens);
+        let mut session = lexer.tokenize("\"${");
+        session.by_ref().count();
+        assert_eq!(&[0, 1, 0], session.mode_stack());
+        let vm = Compiler::new().compile_lexer(&definition).unwrap().with_dfa_cache_size(0);
+        let simulated: Vec<TokenRaw> = vm.tokenize(text).collect();
+        assert_eq!(lexer.tokenize(text).collect::<Vec<TokenRaw>>(), simulated);
+    }
+
+    #[test]
+    fn tokens() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "GREEK", "[\u{3b1}-\u{3c9}]").unwrap(),
+            TokenDefinition::from_pattern(3, "PLUS", r"\+").unwrap(),
+        ]);
+        let lexer = Lexer::new(&definition).unwrap();
+        let tokens: Vec<(usize, usize, &str, &str)> = lexer.tokens("\u{3b1}+?\u{3b2}")
+            .map(|token| (token.start(), token.end(), token.text(), token.name()))
+            .collect();
+        assert_eq!(vec![
+            (0, 2, "\u{3b1}", "GREEK"),
+            (2, 3, "+", "PLUS"),
+            (3, 4, "?", ERROR_TOKEN_NAME),
+            (4, 6, "\u{3b2}", "GREEK"),
+            (6, 6, "", END_TOKEN_NAME),
+        ], tokens);
+    }
+}
@@ -3,9 +3,32 @@
 extern crate core;
 extern crate bit_set;
 
-mod vm;
-mod opcodes;
-mod assembler;
-mod disasm;
-mod ast;
-mod compiler;
\ No newline at end of file
+pub mod vm;
+pub mod opcodes;
+pub mod assembler;
+pub mod disasm;
+pub mod ast;
+pub mod compiler;
+pub mod observer;
+pub mod debugger;
+pub mod classes;
+pub mod dfa;
+pub mod table;
+pub mod codegen;
+pub mod restartable;
+pub mod incremental;
+pub mod stream;
+mod lexer;
+mod utf8;
+
+pub use ast::{Expr, LexerDefinition, TokenDefinition, ModeDefinition, ModeAction, TieBreak, ParseError, parse};
+pub use compiler::CompileError;
+pub use lexer::{Lexer, Token, Tokens, ERROR_TOKEN_NAME, END_TOKEN_NAME};
+pub use dfa::{Dfa, DfaError, LazyDfa};
+pub use table::{DfaTable, TableError};
+pub use debugger::{Debugger, DebugEvent, Breakpoint, StopReason};
+pub use restartable::{RestartableLexer, LexerState, RestartError};
+pub use incremental::{IncrementalLexer, TextEdit, TokenChange, EditError};
+pub use stream::{StreamingSession, TextSource};
+pub use observer::{VmObserver, NoopObserver, TraceObserver};
+pub use vm::{TokenRaw, TokenAction, ERROR_TOKEN_INDEX, END_TOKEN_INDEX};
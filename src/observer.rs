@@ -0,0 +1,55 @@
+use assembler::CodePointer;
+use vm::TokenRaw;
+use bit_set::BitSet;
+
+/// Hooks into `LexingSession`, all callbacks do nothing by default.
+/// Session is generic over observer, so `NoopObserver` is compiled away.
+///
+/// With the DFA cache (see `Vm::with_dfa_cache_size`) threads are simulated only to compute
+/// states, which are not cached yet, so `on_thread_add` is called on cache misses only,
+/// and `on_char_step` gets the threads of the cached state. Use `with_dfa_cache_size(0)`
+/// to observe every thread add
+pub trait VmObserver {
+    /// Longest match search starts at the `position` of the text
+    fn on_match_start(&mut self, _position: usize) {}
+
+    /// Thread with `code_pointer` is added to the thread set, not called for steps over cached DFA states
+    fn on_thread_add(&mut self, _code_pointer: CodePointer) {}
+
+    /// Char at `offset` is fed to the `threads`
+    fn on_char_step(&mut self, _offset: usize, _ch: char, _threads: &BitSet) {}
+
+    /// Token of `token_type_index` type matches text up to `end`
+    fn on_match(&mut self, _token_type_index: u16, _end: usize) {}
+
+    /// Token is produced by the session
+    fn on_token_emit(&mut self, _token: &TokenRaw) {}
+}
+
+/// Observer, which ignores everything
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopObserver;
+
+impl VmObserver for NoopObserver {}
+
+/// Observer, which prints lexing progress to stderr, thread sets of cached DFA states are printed as they were computed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraceObserver;
+
+impl VmObserver for TraceObserver {
+    fn on_match_start(&mut self, position: usize) {
+        eprintln!("Lexing at position {:?}", position);
+    }
+
+    fn on_char_step(&mut self, offset: usize, ch: char, threads: &BitSet) {
+        eprintln!("offset = {}, ch = {:?}, threads = {:?}", offset, ch, threads);
+    }
+
+    fn on_match(&mut self, token_type_index: u16, end: usize) {
+        eprintln!("matched token type {} up to {}", token_type_index, end);
+    }
+
+    fn on_token_emit(&mut self, token: &TokenRaw) {
+        eprintln!("token = {:?}", token);
+    }
+}
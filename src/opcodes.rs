@@ -1,5 +1,5 @@
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     CharImm = 0,
     CharCp = 1,
@@ -12,12 +12,16 @@ pub enum Opcode {
     Invert = 8,
     SplitMany = 9,
     OuterLexer = 10,
-    Noop = 11
+    Noop = 11,
+    /// Split, which targets are stored in constant pool
+    SplitWide = 12,
+    /// Range, which bounds are stored in constant pool as a pair
+    RangeWide = 13
 }
 
 impl Opcode {
-    fn from_instruction(instruction: u32) -> Opcode {
-        return Opcode::from(instruction >> 28)
+    pub fn from_instruction(instruction: u32) -> Opcode {
+        Opcode::from(instruction >> 28)
     }
 }
 
@@ -36,6 +40,8 @@ impl From<u32> for Opcode {
             9 => Opcode::SplitMany,
             10 => Opcode::OuterLexer,
             11 => Opcode::Noop,
+            12 => Opcode::SplitWide,
+            13 => Opcode::RangeWide,
             _ => panic!("Bad opcode")
         }
     }
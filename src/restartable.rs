@@ -0,0 +1,297 @@
+use vm::LexingSession;
+use vm::Vm;
+use vm::END_TOKEN_INDEX;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// Everything the lexer needs to continue from a token boundary, it is an id of the mode stack.
+/// Mode stacks are interned by the `Vm`, so a state is valid for every lexer of the same program.
+/// Ids of `INITIAL` and single mode states are equal to the mode index, other ids are assigned
+/// in order of appearance and are not stable between programs. Lexers reject ids of unknown states
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LexerState(u32);
+
+impl LexerState {
+    /// State of the lexer at the beginning of the text, the initial mode only
+    pub const INITIAL: LexerState = LexerState(0);
+
+    /// State with the id, e.g. a saved one, it is checked when the lexer is started with it
+    pub fn from_id(id: u32) -> Self {
+        LexerState(id)
+    }
+
+    pub fn id(self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestartError {
+    /// State id is not known to the program, e.g. it was produced by a lexer of another program
+    UnknownState { state: LexerState },
+    /// Start offset is after the end offset or the end offset is after the end of the text
+    InvalidRange { start_offset: usize, end_offset: usize },
+    /// Offset is inside of a char
+    NotCharBoundary { offset: usize },
+}
+
+impl fmt::Display for RestartError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RestartError::UnknownState { state } => write!(f, "unknown lexer state {}", state.0),
+            RestartError::InvalidRange { start_offset, end_offset } => write!(f, "invalid range {}..{}", start_offset, end_offset),
+            RestartError::NotCharBoundary { offset } => write!(f, "offset {} is not a char boundary", offset),
+        }
+    }
+}
+
+impl Error for RestartError {}
+
+/// Interned mode stacks, state id is the index of the mode stack
+pub(crate) struct ModeStacks {
+    mode_stacks: Vec<Vec<u16>>,
+    state_ids: HashMap<Vec<u16>, LexerState>,
+}
+
+impl ModeStacks {
+    /// Single mode stacks get ids equal to the mode
+    pub(crate) fn new(mode_count: usize) -> Self {
+        let mut mode_stacks = ModeStacks { mode_stacks: Vec::new(), state_ids: HashMap::new() };
+        for mode in 0..mode_count {
+            mode_stacks.state(&[mode as u16]);
+        }
+        mode_stacks
+    }
+
+    pub(crate) fn state(&mut self, mode_stack: &[u16]) -> LexerState {
+        if let Some(&state) = self.state_ids.get(mode_stack) {
+            return state;
+        }
+        let state = LexerState(self.mode_stacks.len() as u32);
+        self.state_ids.insert(mode_stack.to_vec(), state);
+        self.mode_stacks.push(mode_stack.to_vec());
+        state
+    }
+
+    /// None for the state, which is not interned
+    pub(crate) fn mode_stack(&self, state: LexerState) -> Option<&[u16]> {
+        self.mode_stacks.get(state.0 as usize).map(|mode_stack| &mode_stack[..])
+    }
+}
+
+/// Lexer with the current token, which can be started at any token boundary of the text with the state,
+/// saved for that boundary, e.g. to relex only the changed part of the document in an editor
+pub struct RestartableLexer<'a, 'b> {
+    vm: &'a Vm,
+    session: Option<LexingSession<'a, 'b>>,
+    /// Type of the current token, None after the last token
+    token_type: Option<u16>,
+    token_start: usize,
+    token_end: usize,
+    /// State before the current token
+    state: LexerState,
+}
+
+impl<'a, 'b> RestartableLexer<'a, 'b> {
+    pub fn new(vm: &'a Vm) -> Self {
+        RestartableLexer {
+            vm,
+            session: None,
+            token_type: None,
+            token_start: 0,
+            token_end: 0,
+            state: LexerState::INITIAL,
+        }
+    }
+
+    /// Starts lexing of `text[start_offset..end_offset]` and moves to the first token,
+    /// `start_offset` must be the start of some token, lexed with `initial_state`.
+    /// Fails without changing the lexer, if the state is unknown or the offsets are not valid for the text
+    pub fn start(&mut self, text: &'b str, start_offset: usize, end_offset: usize, initial_state: LexerState) -> Result<(), RestartError> {
+        if start_offset > end_offset || end_offset > text.len() {
+            return Err(RestartError::InvalidRange { start_offset, end_offset });
+        }
+        for &offset in &[start_offset, end_offset] {
+            if !text.is_char_boundary(offset) {
+                return Err(RestartError::NotCharBoundary { offset });
+            }
+        }
+        let mode_stack = match self.vm.state_mode_stack(initial_state) {
+            Some(mode_stack) => mode_stack,
+            None => return Err(RestartError::UnknownState { state: initial_state }),
+        };
+        let text = &text[..end_offset];
+        match self.session {
+            Some(ref mut session) => session.restart(text, start_offset, &mode_stack),
+            None => {
+                let mut session = LexingSession::new(self.vm, text);
+                session.restart(text, start_offset, &mode_stack);
+                self.session = Some(session);
+            }
+        }
+        self.token_end = start_offset;
+        self.advance();
+        Ok(())
+    }
+
+    /// Moves to the next token, does nothing after the last one
+    pub fn advance(&mut self) {
+        let session = match self.session {
+            Some(ref mut session) => session,
+            None => return,
+        };
+        self.state = self.vm.lexer_state(session.mode_stack());
+        self.token_start = self.token_end;
+        match session.next() {
+            Some(token) if token.token_type_index() != END_TOKEN_INDEX => {
+                self.token_type = Some(token.token_type_index());
+                self.token_end = self.token_start + token.length() as usize;
+            }
+            _ => self.token_type = None,
+        }
+    }
+
+    /// Token type index of the current token, None if all tokens are produced
+    pub fn token_type(&self) -> Option<u16> {
+        self.token_type
+    }
+
+    /// Byte offset of the current token start
+    pub fn token_start(&self) -> usize {
+        self.token_start
+    }
+
+    /// Byte offset after the current token end
+    pub fn token_end(&self) -> usize {
+        self.token_end
+    }
+
+    /// State at the start of the current token, lexing restarted there with it produces the same tokens
+    pub fn state(&self) -> LexerState {
+        self.state
+    }
+
+    /// Mode stack of the state, the current mode is the last one, None for an unknown state
+    pub fn mode_stack(&self, state: LexerState) -> Option<Vec<u16>> {
+        self.vm.state_mode_stack(state)
+    }
+
+    pub fn vm(&self) -> &'a Vm {
+        self.vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::LexerDefinition;
+    use ast::TokenDefinition;
+    use ast::ModeDefinition;
+    use ast::ModeAction;
+    use compiler::Compiler;
+
+    /// Lexer of nested block comments: `/*` pushes comment mode, `*/` pops it
+    fn compile() -> Vm {
+        let push = || ModeAction::Push("COMMENT".to_string());
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "WORD", "[a-z]+").unwrap(),
+            TokenDefinition::from_pattern(3, "WS", " +").unwrap(),
+            TokenDefinition::from_pattern(4, "OPEN", r"/\*").unwrap().with_action(push()),
+        ]).with_mode(ModeDefinition::new("COMMENT", vec![
+            TokenDefinition::from_pattern(5, "TEXT", r"[^/*]+|/|\*").unwrap(),
+            TokenDefinition::from_pattern(4, "OPEN", r"/\*").unwrap().with_action(push()),
+            TokenDefinition::from_pattern(6, "CLOSE", r"\*/").unwrap().with_action(ModeAction::Pop),
+        ]));
+        Compiler::new().compile_lexer(&definition).unwrap()
+    }
+
+    /// Tokens until the end: type, start, end and state
+    fn collect(lexer: &mut RestartableLexer) -> Vec<(u16, usize, usize, LexerState)> {
+        let mut tokens = Vec::new();
+        while let Some(token_type) = lexer.token_type() {
+            tokens.push((token_type, lexer.token_start(), lexer.token_end(), lexer.state()));
+            lexer.advance();
+        }
+        tokens
+    }
+
+    #[test]
+    fn same_tokens_as_session() {
+        let vm = compile();
+        let text = "a /* b /* c */ d */ e ?";
+        let mut lexer = RestartableLexer::new(&vm);
+        lexer.start(text, 0, text.len(), LexerState::INITIAL).unwrap();
+        let tokens = collect(&mut lexer);
+        let expected: Vec<u16> = vm.tokenize(text).map(|token| token.token_type_index()).collect();
+        assert_eq!(&expected[..expected.len() - 1], &tokens.iter().map(|token| token.0).collect::<Vec<u16>>()[..]);
+        assert_eq!((None, text.len(), text.len()), (lexer.token_type(), lexer.token_start(), lexer.token_end()));
+        let nested = tokens.iter().find(|token| text[token.1..token.2].trim() == "c").unwrap();
+        assert_eq!(Some(vec![0, 1, 1]), lexer.mode_stack(nested.3));
+    }
+
+    #[test]
+    fn restart_at_every_token() {
+        let vm = compile();
+        let text = "a /* b /* c */ d */ e";
+        let mut lexer = RestartableLexer::new(&vm);
+        lexer.start(text, 0, text.len(), LexerState::INITIAL).unwrap();
+        let tokens = collect(&mut lexer);
+        for (position, &(_, start, _, state)) in tokens.iter().enumerate() {
+            lexer.start(text, start, text.len(), state).unwrap();
+            assert_eq!(&tokens[position..], &collect(&mut lexer)[..], "restart at {}", start);
+        }
+    }
+
+    #[test]
+    fn range() {
+        let vm = compile();
+        let text = "ab cd ef";
+        let mut lexer = RestartableLexer::new(&vm);
+        lexer.start(text, 3, 7, LexerState::INITIAL).unwrap();
+        assert_eq!(vec![(2, 3, 5, LexerState::INITIAL), (3, 5, 6, LexerState::INITIAL), (2, 6, 7, LexerState::INITIAL)], collect(&mut lexer));
+        lexer.start(text, 3, 5, LexerState::from_id(1)).unwrap();
+        assert_eq!(vec![(5, 3, 5, LexerState::from_id(1))], collect(&mut lexer));
+    }
+
+    #[test]
+    fn state_of_another_lexer() {
+        let vm = compile();
+        let text = "a /* b /* c */ d */ e";
+        let mut nested_lexer = RestartableLexer::new(&vm);
+        nested_lexer.start(text, 0, text.len(), LexerState::INITIAL).unwrap();
+        let tokens = collect(&mut nested_lexer);
+        let &(_, start, _, nested) = tokens.iter().max_by_key(|token| token.3.id()).unwrap();
+        assert_eq!(Some(vec![0, 1, 1]), nested_lexer.mode_stack(nested));
+
+        let mut lexer = RestartableLexer::new(&vm);
+        assert_eq!(Some(vec![0, 1, 1]), lexer.mode_stack(nested));
+        lexer.start(text, start, text.len(), nested).unwrap();
+        let position = tokens.iter().position(|token| token.1 == start).unwrap();
+        assert_eq!(&tokens[position..], &collect(&mut lexer)[..]);
+    }
+
+    #[test]
+    fn invalid_start() {
+        let vm = compile();
+        let other_vm = compile();
+        let text = "a /* b /* c */ d */ \u{3b1}";
+        let mut nested_lexer = RestartableLexer::new(&vm);
+        nested_lexer.start(text, 0, text.len(), LexerState::INITIAL).unwrap();
+        let nested = collect(&mut nested_lexer).iter().map(|token| token.3).max_by_key(|state| state.id()).unwrap();
+
+        let mut lexer = RestartableLexer::new(&other_vm);
+        lexer.start(text, 0, 1, LexerState::INITIAL).unwrap();
+        assert_eq!(None, lexer.mode_stack(nested));
+        assert_eq!(Err(RestartError::UnknownState { state: nested }), lexer.start(text, 7, text.len(), nested));
+        assert_eq!(Err(RestartError::UnknownState { state: LexerState::from_id(99) }), lexer.start(text, 0, text.len(), LexerState::from_id(99)));
+        assert_eq!(Err(RestartError::InvalidRange { start_offset: 3, end_offset: 2 }), lexer.start(text, 3, 2, LexerState::INITIAL));
+        assert_eq!(Err(RestartError::InvalidRange { start_offset: 0, end_offset: 99 }), lexer.start(text, 0, 99, LexerState::INITIAL));
+        let inside = text.len() - 1;
+        assert_eq!(Err(RestartError::NotCharBoundary { offset: inside }), lexer.start(text, inside, text.len(), LexerState::INITIAL));
+        assert_eq!(Err(RestartError::NotCharBoundary { offset: inside }), lexer.start(text, 0, inside, LexerState::INITIAL));
+        // failed start keeps the current token
+        assert_eq!((Some(2), 0, 1), (lexer.token_type(), lexer.token_start(), lexer.token_end()));
+    }
+}
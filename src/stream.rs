@@ -0,0 +1,285 @@
+use vm::LexingSession;
+use vm::TokenRaw;
+use vm::Vm;
+use vm::END_TOKEN_INDEX;
+use std::io;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::str;
+
+/// Number of bytes, requested from `io::Read` at once
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Text, which comes in pieces
+pub trait TextSource {
+    /// Appends the next piece of the text to the buffer, returns 0 at the end of the text
+    fn read_text(&mut self, buffer: &mut String) -> io::Result<usize>;
+}
+
+/// Text source over `io::Read`, chars split between reads are joined, invalid UTF-8 is reported as `InvalidData`
+pub struct ReaderSource<R: Read> {
+    reader: R,
+    bytes: Vec<u8>,
+    /// Number of bytes of incomplete char at the start of `bytes`
+    incomplete: usize,
+}
+
+impl<R: Read> ReaderSource<R> {
+    pub fn new(reader: R) -> Self {
+        ReaderSource { reader, bytes: vec![0; READ_CHUNK_SIZE], incomplete: 0 }
+    }
+}
+
+impl<R: Read> TextSource for ReaderSource<R> {
+    fn read_text(&mut self, buffer: &mut String) -> io::Result<usize> {
+        loop {
+            let read = match self.reader.read(&mut self.bytes[self.incomplete..]) {
+                Ok(read) => read,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            if read == 0 {
+                if self.incomplete > 0 {
+                    return Err(io::Error::new(ErrorKind::InvalidData, "stream ends in the middle of UTF-8 sequence"));
+                }
+                return Ok(0);
+            }
+            let len = self.incomplete + read;
+            let valid = match str::from_utf8(&self.bytes[..len]) {
+                Ok(text) => text.len(),
+                // error without length means that the sequence at the end is not finished yet
+                Err(error) if error.error_len().is_none() => error.valid_up_to(),
+                Err(_) => return Err(io::Error::new(ErrorKind::InvalidData, "stream is not valid UTF-8")),
+            };
+            buffer.push_str(str::from_utf8(&self.bytes[..valid]).unwrap());
+            self.bytes.copy_within(valid..len, 0);
+            self.incomplete = len - valid;
+            if valid > 0 {
+                return Ok(valid);
+            }
+        }
+    }
+}
+
+/// Text source over an iterator of string pieces
+pub struct ChunkSource<I> {
+    chunks: I,
+}
+
+impl<I> ChunkSource<I> {
+    pub fn new(chunks: I) -> Self {
+        ChunkSource { chunks }
+    }
+}
+
+impl<I: Iterator> TextSource for ChunkSource<I> where I::Item: AsRef<str> {
+    fn read_text(&mut self, buffer: &mut String) -> io::Result<usize> {
+        for chunk in &mut self.chunks {
+            let chunk = chunk.as_ref();
+            if !chunk.is_empty() {
+                buffer.push_str(chunk);
+                return Ok(chunk.len());
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Lexes text, which is pulled from the source piece by piece. Only text from the start of the current token
+/// to the furthest char examined by the lexer is buffered, so token is produced, when the lexer can't be
+/// affected by the text, which is not read yet
+pub struct StreamingSession<'a, S: TextSource> {
+    source: S,
+    /// Session without text between calls of `next_token`
+    session: Option<LexingSession<'a, 'static>>,
+    buffer: String,
+    /// Offset of the buffer start in the whole text
+    buffer_start: usize,
+    /// Offset of the next token in the whole text
+    position: usize,
+    /// Offset of the last token in the whole text
+    token_start: usize,
+    token_length: usize,
+    /// Mode stack before the next token
+    mode_stack: Vec<u16>,
+    is_source_end: bool,
+    is_end: bool,
+}
+
+impl<'a, R: Read> StreamingSession<'a, ReaderSource<R>> {
+    pub fn from_reader(vm: &'a Vm, reader: R) -> Self {
+        StreamingSession::new(vm, ReaderSource::new(reader))
+    }
+}
+
+impl<'a, I: Iterator> StreamingSession<'a, ChunkSource<I>> where I::Item: AsRef<str> {
+    pub fn from_chunks(vm: &'a Vm, chunks: I) -> Self {
+        StreamingSession::new(vm, ChunkSource::new(chunks))
+    }
+}
+
+impl<'a, S: TextSource> StreamingSession<'a, S> {
+    pub fn new(vm: &'a Vm, source: S) -> Self {
+        StreamingSession {
+            source,
+            session: Some(LexingSession::new(vm, "")),
+            buffer: String::new(),
+            buffer_start: 0,
+            position: 0,
+            token_start: 0,
+            token_length: 0,
+            mode_stack: vec![0],
+            is_source_end: false,
+            is_end: false,
+        }
+    }
+
+    /// Produces the same tokens as `LexingSession` over the whole text, None after the token of `END_TOKEN_INDEX` type
+    pub fn next_token(&mut self) -> io::Result<Option<TokenRaw>> {
+        if self.is_end {
+            return Ok(None);
+        }
+        loop {
+            let offset = self.position - self.buffer_start;
+            let mut session = self.session.take().unwrap().with_text(&self.buffer, offset, &self.mode_stack);
+            let token = session.next().unwrap();
+            // token is final, if the lexer hasn't reached the end of the buffer
+            let is_final = self.is_source_end || (token.token_type_index() != END_TOKEN_INDEX && session.scan_end() <= self.buffer.len());
+            if is_final {
+                self.mode_stack.clear();
+                self.mode_stack.extend_from_slice(session.mode_stack());
+            }
+            self.session = Some(session.with_text("", 0, &[0]));
+            if is_final {
+                self.token_start = self.position;
+                self.token_length = token.length() as usize;
+                self.position += self.token_length;
+                self.is_end = token.token_type_index() == END_TOKEN_INDEX;
+                return Ok(Some(token));
+            }
+            self.read_more()?;
+        }
+    }
+
+    /// Offset of the last token in the whole text
+    pub fn token_start(&self) -> usize {
+        self.token_start
+    }
+
+    /// Text of the last token
+    pub fn token_text(&self) -> &str {
+        let start = self.token_start - self.buffer_start;
+        &self.buffer[start..start + self.token_length]
+    }
+
+    /// Mode stack before the next token
+    pub fn mode_stack(&self) -> &[u16] {
+        &self.mode_stack
+    }
+
+    /// Drops text before the next token and appends pieces of the text, until the rest of the buffer is
+    /// doubled, so the text of a long token is rescanned only a logarithmic number of times
+    fn read_more(&mut self) -> io::Result<()> {
+        let consumed = self.position - self.buffer_start;
+        self.buffer.drain(..consumed);
+        self.buffer_start = self.position;
+        let target_len = (2 * self.buffer.len()).max(self.buffer.len() + 1);
+        while self.buffer.len() < target_len {
+            if self.source.read_text(&mut self.buffer)? == 0 {
+                self.is_source_end = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, S: TextSource> Iterator for StreamingSession<'a, S> {
+    type Item = io::Result<TokenRaw>;
+
+    fn next(&mut self) -> Option<io::Result<TokenRaw>> {
+        self.next_token().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::LexerDefinition;
+    use ast::TokenDefinition;
+    use compiler::Compiler;
+
+    fn compile() -> Vm {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "WORD", "[a-z\u{3b1}-\u{3c9}]+").unwrap(),
+            TokenDefinition::from_pattern(3, "WS", " +").unwrap(),
+            TokenDefinition::from_pattern(4, "STRING", "\"[^\"]*\"").unwrap(),
+            TokenDefinition::from_pattern(5, "EMOJI", "\u{1F600}").unwrap(),
+        ]);
+        Compiler::new().compile_lexer(&definition).unwrap()
+    }
+
+    /// Reader, which returns at most `step` bytes at once
+    struct SlowReader<'a> {
+        bytes: &'a [u8],
+        step: usize,
+    }
+
+    impl<'a> Read for SlowReader<'a> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let len = self.step.min(buffer.len()).min(self.bytes.len());
+            buffer[..len].copy_from_slice(&self.bytes[..len]);
+            self.bytes = &self.bytes[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let vm = compile();
+        let text = "ab \"c d\" ?? \u{3b1}\u{3b2}\u{1F600} \"x";
+        let expected: Vec<TokenRaw> = vm.tokenize(text).collect();
+        let pieces = vec!["a", "b \"c", " ", "d\" ?", "?", " \u{3b1}", "", "\u{3b2}\u{1F600} \"", "x"];
+        assert_eq!(text, pieces.concat());
+        let tokens: Vec<TokenRaw> = StreamingSession::from_chunks(&vm, pieces.into_iter()).collect::<io::Result<_>>().unwrap();
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn reader() {
+        let vm = compile();
+        let text = "\u{3b1}\u{3b2} \u{1F600}\u{1F600}?? \"\u{3c9}\" end";
+        let expected: Vec<TokenRaw> = vm.tokenize(text).collect();
+        for step in 1..6 {
+            let mut session = StreamingSession::from_reader(&vm, SlowReader { bytes: text.as_bytes(), step });
+            let mut tokens = Vec::new();
+            while let Some(token) = session.next_token().unwrap() {
+                assert_eq!(&text[session.token_start()..session.token_start() + token.length() as usize], session.token_text());
+                tokens.push(token);
+            }
+            assert_eq!(expected, tokens, "step: {}", step);
+        }
+    }
+
+    #[test]
+    fn long_token() {
+        // rescanning the whole token after every byte would take minutes
+        let vm = compile();
+        let len = 200_000;
+        let text = format!("ab \"{}\" c", "x".repeat(len));
+        let expected: Vec<TokenRaw> = vm.tokenize(&text).collect();
+        let pieces = (0..text.len()).map(|index| &text[index..index + 1]);
+        let tokens: Vec<TokenRaw> = StreamingSession::from_chunks(&vm, pieces).collect::<io::Result<_>>().unwrap();
+        assert_eq!(expected, tokens);
+        assert_eq!(len as u32 + 2, tokens[2].length());
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let vm = compile();
+        let mut session = StreamingSession::from_reader(&vm, &b"ab \xFF"[..]);
+        assert_eq!(ErrorKind::InvalidData, session.next().unwrap().unwrap_err().kind());
+        let mut session = StreamingSession::from_reader(&vm, &b"ab \xCE"[..]);
+        assert_eq!(ErrorKind::InvalidData, session.nth(1).unwrap().unwrap_err().kind());
+    }
+}
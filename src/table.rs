@@ -0,0 +1,206 @@
+use assembler::ProgramData;
+use dfa::Dfa;
+use dfa::DfaError;
+use dfa::StateId;
+use dfa::START_STATE;
+use dfa::DEAD_STATE;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+
+/// First line of the text form of `DfaTable`
+const HEADER: &str = "lexer-vm dfa 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableError {
+    /// Line of the text form can't be parsed, lines are numbered from 1
+    Malformed { line: usize },
+    /// First class doesn't start at 0, classes are not sorted or some class starts after the last char
+    InvalidClasses,
+    /// Table doesn't have dead and start states or its size doesn't match number of states and classes
+    InvalidSize,
+    /// Transition leads to the state, which doesn't exist
+    InvalidState { state: StateId },
+    /// Dead state accepts a token or leads to another state
+    InvalidDeadState,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TableError::Malformed { line } => write!(f, "malformed table at line {}", line),
+            TableError::InvalidClasses => write!(f, "char classes are not sorted from 0"),
+            TableError::InvalidSize => write!(f, "table size doesn't match number of states and classes"),
+            TableError::InvalidState { state } => write!(f, "transition to missing state {}", state),
+            TableError::InvalidDeadState => write!(f, "dead state accepts or leaves itself"),
+        }
+    }
+}
+
+impl Error for TableError {}
+
+/// Plain form of `Dfa`, which can be saved and loaded back.
+///
+/// Text form:
+/// ```text
+/// lexer-vm dfa 1
+/// classes <first code point of every class>
+/// states <number of states>
+/// <accepted token type index or -> <next state for every class>
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DfaTable {
+    /// First code points of char classes
+    pub class_starts: Vec<u32>,
+    /// Next state by `state * class_starts.len() + class`
+    pub transitions: Vec<StateId>,
+    /// Token type, matched after entering the state
+    pub accepts: Vec<Option<u16>>,
+}
+
+impl DfaTable {
+    /// Minimized table of the program
+    pub fn from_program(program: &ProgramData) -> Result<Self, DfaError> {
+        Ok(Dfa::from_program(program)?.minimize().to_table())
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.accepts.len()
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.class_starts.len()
+    }
+
+    pub fn validate(&self) -> Result<(), TableError> {
+        if self.class_starts.first() != Some(&0)
+            || self.class_starts.windows(2).any(|pair| pair[0] >= pair[1])
+            || self.class_starts.last().is_some_and(|&start| start > char::MAX as u32) {
+            return Err(TableError::InvalidClasses);
+        }
+        if self.state_count() <= START_STATE as usize || self.transitions.len() != self.state_count() * self.class_count() {
+            return Err(TableError::InvalidSize);
+        }
+        if let Some(&state) = self.transitions.iter().find(|&&state| state as usize >= self.state_count()) {
+            return Err(TableError::InvalidState { state });
+        }
+        let dead = DEAD_STATE as usize;
+        if self.accepts[dead].is_some() || self.transitions[dead * self.class_count()..(dead + 1) * self.class_count()].iter().any(|&state| state != DEAD_STATE) {
+            return Err(TableError::InvalidDeadState);
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        write!(writer, "classes")?;
+        for start in &self.class_starts {
+            write!(writer, " {}", start)?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "states {}", self.state_count())?;
+        let class_count = self.class_count();
+        for (state, accept) in self.accepts.iter().enumerate() {
+            match accept {
+                Some(token_type_index) => write!(writer, "{}", token_type_index)?,
+                None => write!(writer, "-")?,
+            }
+            for next in &self.transitions[state * class_count..(state + 1) * class_count] {
+                write!(writer, " {}", next)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut buffer = Vec::new();
+        self.write(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    /// Parses the text form, produced by `write`
+    pub fn parse(text: &str) -> Result<Self, TableError> {
+        let mut lines = text.lines().enumerate().map(|(index, line)| (index + 1, line));
+        let mut next_line = |expected_prefix: &str| -> Result<(usize, Vec<String>), TableError> {
+            let (number, line) = lines.next().ok_or(TableError::Malformed { line: 0 })?;
+            let mut words = line.split_whitespace().map(|word| word.to_string());
+            if !expected_prefix.is_empty() && words.next().as_deref() != Some(expected_prefix) {
+                return Err(TableError::Malformed { line: number });
+            }
+            Ok((number, words.collect()))
+        };
+        let (number, header) = next_line("")?;
+        if header.join(" ") != HEADER {
+            return Err(TableError::Malformed { line: number });
+        }
+        let (number, starts) = next_line("classes")?;
+        let class_starts = parse_numbers(&starts, number)?;
+        let (number, count) = next_line("states")?;
+        let state_count: Vec<usize> = parse_numbers(&count, number)?;
+        if state_count.len() != 1 {
+            return Err(TableError::Malformed { line: number });
+        }
+        let mut transitions = Vec::new();
+        let mut accepts = Vec::new();
+        for _ in 0..state_count[0] {
+            let (number, words) = next_line("")?;
+            let (accept, targets) = words.split_first().ok_or(TableError::Malformed { line: number })?;
+            accepts.push(if accept == "-" { None } else { Some(parse_number(accept, number)?) });
+            if targets.len() != class_starts.len() {
+                return Err(TableError::Malformed { line: number });
+            }
+            transitions.extend(parse_numbers::<StateId>(targets, number)?);
+        }
+        let table = DfaTable { class_starts, transitions, accepts };
+        table.validate()?;
+        Ok(table)
+    }
+}
+
+fn parse_number<T: FromStr>(word: &str, line: usize) -> Result<T, TableError> {
+    word.parse().map_err(|_| TableError::Malformed { line })
+}
+
+fn parse_numbers<T: FromStr>(words: &[String], line: usize) -> Result<Vec<T>, TableError> {
+    words.iter().map(|word| parse_number(word, line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::LexerDefinition;
+    use ast::TokenDefinition;
+    use compiler::Compiler;
+
+    fn program(patterns: &[(&str, &str)]) -> ProgramData {
+        let tokens = patterns.iter().enumerate()
+            .map(|(position, (name, pattern))| TokenDefinition::from_pattern(position as u16 + 2, name, pattern).unwrap())
+            .collect();
+        let mut compiler = Compiler::new();
+        compiler.generate_lexer(&LexerDefinition::new(tokens)).unwrap();
+        compiler.get_prog_data().unwrap()
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let table = DfaTable::from_program(&program(&[("NUM", "[0-9]+"), ("PLUS", "\\+")])).unwrap();
+        let text = table.to_text();
+        assert!(text.starts_with("lexer-vm dfa 1\nclasses 0 43 44 48 58\nstates 4\n"));
+        assert_eq!(table, DfaTable::parse(&text).unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(TableError::Malformed { line: 1 }), DfaTable::parse("dfa\n"));
+        assert_eq!(Err(TableError::Malformed { line: 4 }), DfaTable::parse("lexer-vm dfa 1\nclasses 0 5\nstates 2\n- 0\n"));
+        assert_eq!(Err(TableError::InvalidClasses), DfaTable::parse("lexer-vm dfa 1\nclasses 5\nstates 2\n- 0\n- 0\n"));
+        assert_eq!(Err(TableError::InvalidState { state: 2 }), DfaTable::parse("lexer-vm dfa 1\nclasses 0\nstates 2\n- 0\n2 2\n"));
+        assert_eq!(Err(TableError::InvalidClasses), DfaTable::parse("lexer-vm dfa 1\nclasses 0 1114112\nstates 2\n- 0 0\n- 0 0\n"));
+        assert_eq!(Err(TableError::InvalidDeadState), DfaTable::parse("lexer-vm dfa 1\nclasses 0\nstates 2\n- 1\n2 1\n"));
+        assert_eq!(Err(TableError::InvalidDeadState), DfaTable::parse("lexer-vm dfa 1\nclasses 0\nstates 2\n2 0\n2 1\n"));
+    }
+}
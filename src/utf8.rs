@@ -0,0 +1,96 @@
+const SURROGATES_START: u32 = 0xD800;
+const SURROGATES_END: u32 = 0xDFFF;
+/// Last code points of 1, 2 and 3 byte long encodings
+const LENGTH_BOUNDARIES: [u32; 3] = [0x7F, 0x7FF, 0xFFFF];
+
+/// Inclusive byte ranges, matching encoded chars one byte after another
+pub type Utf8Sequence = Vec<(u8, u8)>;
+
+/// Byte sequences, which match UTF-8 encodings of exactly the chars in the inclusive range,
+/// no encoding is matched by two sequences
+pub fn utf8_sequences(from: char, to: char) -> Vec<Utf8Sequence> {
+    let mut sequences = Vec::new();
+    let (from, to) = (from as u32, to as u32);
+    if from <= SURROGATES_END && SURROGATES_START <= to {
+        if from < SURROGATES_START {
+            split(from, SURROGATES_START - 1, &mut sequences);
+        }
+        if to > SURROGATES_END {
+            split(SURROGATES_END + 1, to, &mut sequences);
+        }
+    } else {
+        split(from, to, &mut sequences);
+    }
+    sequences
+}
+
+/// Splits the range until bounds have the same encoded length and every continuation byte
+/// of the range spans the whole 0x80-0xBF interval, except for the common prefix
+fn split(from: u32, to: u32, sequences: &mut Vec<Utf8Sequence>) {
+    for &boundary in &LENGTH_BOUNDARIES {
+        if from <= boundary && boundary < to {
+            split(from, boundary, sequences);
+            split(boundary + 1, to, sequences);
+            return;
+        }
+    }
+    for continuation_count in 1..4 {
+        let mask = (1 << (6 * continuation_count)) - 1;
+        if from & !mask != to & !mask {
+            if from & mask != 0 {
+                split(from, from | mask, sequences);
+                split((from | mask) + 1, to, sequences);
+                return;
+            }
+            if to & mask != mask {
+                split(from, (to & !mask) - 1, sequences);
+                split(to & !mask, to, sequences);
+                return;
+            }
+        }
+    }
+    let from_bytes = encode(from);
+    let to_bytes = encode(to);
+    sequences.push(from_bytes.iter().cloned().zip(to_bytes.iter().cloned()).collect());
+}
+
+fn encode(code_point: u32) -> Vec<u8> {
+    let mut buffer = [0; 4];
+    let ch = ::std::char::from_u32(code_point).unwrap();
+    ch.encode_utf8(&mut buffer).as_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(sequences: &[Utf8Sequence], bytes: &[u8]) -> usize {
+        sequences.iter()
+            .filter(|sequence| sequence.len() == bytes.len()
+                && sequence.iter().zip(bytes).all(|(&(from, to), &byte)| from <= byte && byte <= to))
+            .count()
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(vec![vec![(0x61, 0x7A)]], utf8_sequences('a', 'z'));
+        assert_eq!(vec![vec![(0xC3, 0xC3), (0xA9, 0xA9)]], utf8_sequences('\u{E9}', '\u{E9}'));
+        assert_eq!(vec![vec![(0x00, 0x7F)], vec![(0xC2, 0xDF), (0x80, 0xBF)]], utf8_sequences('\u{0}', '\u{7FF}'));
+    }
+
+    #[test]
+    fn every_char_matched_once() {
+        let ranges = [('\u{0}', ::std::char::MAX), ('\u{3B1}', '\u{3C9}'), ('\u{7F}', '\u{10000}'), ('\u{D000}', '\u{E000}'), ('\u{1F600}', '\u{1F64F}')];
+        for &(from, to) in &ranges {
+            let sequences = utf8_sequences(from, to);
+            let (from, to) = (from as u32, to as u32);
+            for code_point in (0..::std::char::MAX as u32 + 1).step_by(7).chain(vec![from, to, 0xFFFF, 0x10000]) {
+                if let Some(ch) = ::std::char::from_u32(code_point) {
+                    let expected = if from <= code_point && code_point <= to { 1 } else { 0 };
+                    let mut buffer = [0; 4];
+                    assert_eq!(expected, matches(&sequences, ch.encode_utf8(&mut buffer).as_bytes()), "{:X} in {:X}-{:X}", code_point, from, to);
+                }
+            }
+        }
+    }
+}
@@ -1,217 +1,598 @@
 use assembler::CodePointer;
-use opcodes::Opcode;
 use disasm::decode;
 use disasm::Instruction;
+use debugger::DebugInfo;
+use debugger::Debugger;
+use classes::CharClasses;
+use classes::ClassSet;
+use dfa::LazyDfa;
+use observer::NoopObserver;
+use observer::VmObserver;
+use restartable::LexerState;
+use restartable::ModeStacks;
 use std::{
-    fmt::Debug,
-    fmt::Formatter,
-    fmt,
-    mem::swap,
-    borrow::Borrow,
-    iter,
     cmp::max,
+    mem::swap,
+    str,
+    str::Utf8Error,
+    sync::Mutex,
 };
 use bit_set::BitSet;
 
 
+/// Compiled lexer program, lexing state lives in `LexingSession`, so one Vm may serve many sessions
 pub struct Vm {
     code: Vec<u32>,
     constant_pool: Vec<u32>,
-    // TODO put threads to LexerSession
-    current_threads: BitSet,
-    next_threads: BitSet,
+    /// See `ProgramData::token_ranks`
+    token_ranks: Vec<u32>,
+    debug_info: DebugInfo,
+    /// Char classes of the program, transitions of `LazyDfa` are stored by them
+    classes: CharClasses,
+    /// Classes, matched by the instruction at every code pointer
+    class_sets: Vec<ClassSet>,
+    /// Limit of states of `LazyDfa` in every session, 0 disables the cache
+    dfa_cache_size: usize,
+    /// Code pointers, where threads start in every mode
+    mode_entries: Vec<CodePointer>,
+    /// See `ProgramData::token_actions`
+    token_actions: Vec<Vec<TokenAction>>,
+    /// See `ProgramData::byte_mode`
+    byte_mode: bool,
+    /// Mode stacks of `LexerState`s, they are shared by all lexers of the program
+    mode_stacks: Mutex<ModeStacks>,
 }
 
+/// Change of the mode stack after the token, modes are referred by index, the initial mode is 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenAction {
+    None,
+    Push(u16),
+    Pop,
+    Switch(u16),
+}
+
+/// Default limit of states of `LazyDfa`, which is used by `LexingSession`
+pub const DEFAULT_DFA_CACHE_SIZE: usize = 1000;
+
 
 impl Vm {
     pub fn new(code: Vec<u32>, constant_pool: Vec<u32>) -> Self {
-        let code_len = code.len();
-        let mut current_threads = BitSet::with_capacity(code_len);
+        let classes = CharClasses::from_program(&code, &constant_pool);
+        let class_sets = code.iter()
+            .map(|&instruction| classes.class_set(&decode(instruction), &constant_pool))
+            .collect();
+        let mode_entries = match code.first().map(|&instruction| decode(instruction)) {
+            Some(Instruction::OuterLexer { table_index }) => {
+                let table_start = table_index as usize + 1;
+                constant_pool[table_start..table_start + constant_pool[table_index as usize] as usize].to_vec()
+            }
+            _ => vec![0],
+        };
+        let mode_stacks = Mutex::new(ModeStacks::new(mode_entries.len()));
         Vm {
             code,
             constant_pool,
-            current_threads,
-            next_threads: BitSet::with_capacity(code_len),
+            token_ranks: Vec::new(),
+            debug_info: DebugInfo::default(),
+            classes,
+            class_sets,
+            dfa_cache_size: DEFAULT_DFA_CACHE_SIZE,
+            mode_entries,
+            token_actions: Vec::new(),
+            byte_mode: false,
+            mode_stacks,
+        }
+    }
+
+    /// Sets limit of DFA states, which are cached by every session, 0 means threads simulation only
+    pub fn with_dfa_cache_size(mut self, dfa_cache_size: usize) -> Self {
+        self.dfa_cache_size = dfa_cache_size;
+        self
+    }
+
+    /// Sets ranks of token types, without them token type with greater index wins on equal length match
+    pub fn with_token_ranks(mut self, token_ranks: Vec<u32>) -> Self {
+        self.token_ranks = token_ranks;
+        self
+    }
+
+    /// Sets mode stack changes by mode and token type index, without them lexing stays in the initial mode
+    pub fn with_token_actions(mut self, token_actions: Vec<Vec<TokenAction>>) -> Self {
+        self.token_actions = token_actions;
+        self
+    }
+
+    /// Marks the program as compiled in byte mode, its sessions step over bytes instead of chars
+    pub fn with_byte_mode(mut self, byte_mode: bool) -> Self {
+        self.byte_mode = byte_mode;
+        self
+    }
+
+    pub fn is_byte_mode(&self) -> bool {
+        self.byte_mode
+    }
+
+    pub fn with_debug_info(mut self, debug_info: DebugInfo) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    pub fn code(&self) -> &[u32] {
+        &self.code
+    }
+
+    pub fn constant_pool(&self) -> &[u32] {
+        &self.constant_pool
+    }
+
+    pub fn classes(&self) -> &CharClasses {
+        &self.classes
+    }
+
+    /// Number of lexer modes, program without OuterLexer has the initial mode only
+    pub fn mode_count(&self) -> usize {
+        self.mode_entries.len()
+    }
+
+    /// Code pointer, where threads of the mode start
+    pub fn mode_entry(&self, mode: u16) -> CodePointer {
+        self.mode_entries[mode as usize]
+    }
+
+    /// Mode stack change after the token, matched in the mode
+    pub fn token_action(&self, mode: u16, token_type_index: u16) -> TokenAction {
+        self.token_actions.get(mode as usize)
+            .and_then(|actions| actions.get(token_type_index as usize))
+            .cloned()
+            .unwrap_or(TokenAction::None)
+    }
+
+    /// True, if some token changes the mode stack
+    pub fn has_token_actions(&self) -> bool {
+        self.token_actions.iter().flatten().any(|&action| action != TokenAction::None)
+    }
+
+    /// Applies action of the token to the mode stack, the bottom mode is never popped
+    pub(crate) fn apply_token_action(&self, mode_stack: &mut Vec<u16>, token_type_index: u16) {
+        let mode = *mode_stack.last().unwrap();
+        match self.token_action(mode, token_type_index) {
+            TokenAction::None => {}
+            TokenAction::Push(next) => mode_stack.push(next),
+            TokenAction::Pop => {
+                if mode_stack.len() > 1 {
+                    mode_stack.pop();
+                }
+            }
+            TokenAction::Switch(next) => *mode_stack.last_mut().unwrap() = next,
+        }
+    }
+
+    /// State with the mode stack, equal mode stacks get the same state in all lexers of the program
+    pub(crate) fn lexer_state(&self, mode_stack: &[u16]) -> LexerState {
+        self.mode_stacks.lock().unwrap().state(mode_stack)
+    }
+
+    /// Mode stack of the state, the current mode is the last one, None if no lexer of the program produced the state
+    pub fn state_mode_stack(&self, state: LexerState) -> Option<Vec<u16>> {
+        self.mode_stacks.lock().unwrap().mode_stack(state).map(|mode_stack| mode_stack.to_vec())
+    }
+
+    fn token_rank(&self, token_type_index: u16) -> u32 {
+        self.token_ranks.get(token_type_index as usize).cloned().unwrap_or(token_type_index as u32)
+    }
+
+    /// Chooses token type, which wins on equal length match
+    pub(crate) fn best_token(&self, left: Option<u16>, right: Option<u16>) -> Option<u16> {
+        match (left, right) {
+            (Some(left_index), Some(right_index)) => {
+                if self.token_rank(right_index) > self.token_rank(left_index) { right } else { left }
+            }
+            (None, _) => right,
+            (_, None) => left,
         }
     }
+
+    /// Starts lexing of the text, tokens are produced lazily by the returned iterator
+    pub fn tokenize<'a, 'b>(&'a self, text: &'b str) -> LexingSession<'a, 'b> {
+        LexingSession::new(self, text)
+    }
+
+    /// Starts lexing of bytes. A program compiled in byte mode accepts any bytes,
+    /// otherwise the bytes must be valid UTF-8 and are lexed as the decoded text
+    pub fn tokenize_bytes<'a, 'b>(&'a self, bytes: &'b [u8]) -> Result<LexingSession<'a, 'b>, Utf8Error> {
+        if !self.byte_mode {
+            str::from_utf8(bytes)?;
+        }
+        Ok(LexingSession::from_bytes(self, bytes, NoopObserver))
+    }
+
+    /// Starts step by step lexing of the text
+    pub fn debug<'a, 'b>(&'a self, text: &'b str) -> Debugger<'a, 'b> {
+        Debugger::new(self, text)
+    }
+
+    /// Like `tokenize`, but reports lexing progress to the `observer`, see `VmObserver` about the DFA cache
+    pub fn tokenize_observed<'a, 'b, O: VmObserver>(&'a self, text: &'b str, observer: O) -> LexingSession<'a, 'b, O> {
+        LexingSession::with_observer(self, text, observer)
+    }
 }
 
 
-struct LexingSession<'a, 'b> {
-    vm: &'a mut Vm,
-    token_start: u32,
-    text: &'b str,
+/// Lexes the text char by char or, if the program is compiled in byte mode, byte by byte,
+/// offsets and token lengths are in bytes anyway
+pub struct LexingSession<'a, 'b, O: VmObserver = NoopObserver> {
+    vm: &'a Vm,
+    /// UTF-8 text or arbitrary bytes in byte mode
+    text: &'b [u8],
+    /// Start of the next token
     position: usize,
     is_end: bool,
+    /// Longest match at `position` and end of its scan, if it is already known
+    lookahead: Option<(BestToken, usize)>,
+    /// See `scan_end`
+    scan_end: usize,
+    current_threads: BitSet,
+    next_threads: BitSet,
+    /// Buffer for code pointers of threads, advanced by the current char
+    advanced_threads: Vec<CodePointer>,
+    /// Buffer for instructions, followed while threads are added
+    visited: BitSet,
+    /// Cached thread sets, None if the cache is disabled or thrashing
+    dfa: Option<LazyDfa>,
+    /// Modes, entered by tokens, tokens are matched in the last one
+    mode_stack: Vec<u16>,
+    observer: O,
 }
 
 
+/// Longest match found so far
 #[derive(Copy, Clone)]
 struct BestToken {
     token_index: u16,
+    /// Offset after the last char of the match
+    end: usize,
 }
 
 impl<'a, 'b> LexingSession<'a, 'b> {
-    pub fn new(vm: &'a mut Vm, text: &'b str) -> Self {
+    pub fn new(vm: &'a Vm, text: &'b str) -> Self {
+        LexingSession::with_observer(vm, text, NoopObserver)
+    }
+}
+
+impl<'a, 'b, O: VmObserver> LexingSession<'a, 'b, O> {
+    pub fn with_observer(vm: &'a Vm, text: &'b str, observer: O) -> Self {
+        LexingSession::from_bytes(vm, text.as_bytes(), observer)
+    }
+
+    /// Session over bytes, which must be valid UTF-8 unless the program is compiled in byte mode
+    fn from_bytes(vm: &'a Vm, text: &'b [u8], observer: O) -> Self {
+        let code_len = vm.code.len();
         LexingSession {
             vm,
-            token_start: 0,
             text,
             position: 0,
-            is_end: false
+            is_end: false,
+            lookahead: None,
+            scan_end: 0,
+            current_threads: BitSet::with_capacity(code_len),
+            next_threads: BitSet::with_capacity(code_len),
+            advanced_threads: Vec::new(),
+            visited: BitSet::with_capacity(code_len),
+            dfa: if vm.dfa_cache_size > 0 {
+                Some(LazyDfa::new(vm.classes.len(), vm.dfa_cache_size))
+            } else {
+                None
+            },
+            mode_stack: vec![0],
+            observer,
+        }
+    }
+
+    /// Mode, in which the next token is matched
+    pub fn mode(&self) -> u16 {
+        *self.mode_stack.last().unwrap()
+    }
+
+    pub fn mode_stack(&self) -> &[u16] {
+        &self.mode_stack
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn into_observer(self) -> O {
+        self.observer
+    }
+
+    /// True, if thread sets are cached as DFA states. Cache is dropped, when it is cleared too often
+    pub fn uses_dfa_cache(&self) -> bool {
+        self.dfa.is_some()
+    }
+
+    /// Continues lexing of the text from `position` with the mode stack, keeping cached DFA states.
+    /// `position` must be a token boundary, otherwise tokens may differ from lexing from the beginning
+    pub fn restart(&mut self, text: &'b str, position: usize, mode_stack: &[u16]) {
+        assert!(!mode_stack.is_empty(), "mode stack must contain the initial mode");
+        self.text = text.as_bytes();
+        self.position = position;
+        self.is_end = false;
+        self.lookahead = None;
+        self.scan_end = position;
+        self.mode_stack.clear();
+        self.mode_stack.extend_from_slice(mode_stack);
+    }
+
+    /// Like `restart`, but moves the session with its buffers and cached DFA states to the text of another lifetime
+    pub fn with_text<'c>(self, text: &'c str, position: usize, mode_stack: &[u16]) -> LexingSession<'a, 'c, O> {
+        let mut session = LexingSession {
+            vm: self.vm,
+            text: text.as_bytes(),
+            position,
+            is_end: false,
+            lookahead: None,
+            scan_end: 0,
+            current_threads: self.current_threads,
+            next_threads: self.next_threads,
+            advanced_threads: self.advanced_threads,
+            visited: self.visited,
+            dfa: self.dfa,
+            mode_stack: self.mode_stack,
+            observer: self.observer,
+        };
+        session.restart(text, position, mode_stack);
+        session
+    }
+
+    /// Start of the next token
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Offset after the last char, which was examined to produce the last token, the start position before
+    /// the first token. It is greater than the text length, if threads were alive at the end of the text.
+    /// Token stays the same after any edit of the text after this offset
+    pub fn scan_end(&self) -> usize {
+        self.scan_end
+    }
+
+    /// Runs threads from `start` until all of them die, remembering the last accepting position.
+    /// Also returns offset after the last examined char, see `scan_end`
+    fn longest_match(&mut self, start: usize) -> (Option<BestToken>, usize) {
+        self.observer.on_match_start(start);
+        if self.dfa.as_ref().is_some_and(|dfa| dfa.is_thrashing()) {
+            self.dfa = None;
+        }
+        if self.dfa.is_some() {
+            self.longest_match_cached(start)
+        } else {
+            self.longest_match_simulated(start)
+        }
+    }
+
+    /// Same as `longest_match_simulated`, but thread sets are memoized as DFA states,
+    /// so threads are added only for transitions, which are not cached yet
+    fn longest_match_cached(&mut self, start: usize) -> (Option<BestToken>, usize) {
+        let vm = self.vm;
+        let mut scan_end = self.text.len() + 1;
+        let dfa = match self.dfa {
+            Some(ref mut dfa) => dfa,
+            None => return (None, scan_end),
+        };
+        let mut state = dfa.start_state(vm, *self.mode_stack.last().unwrap(), &mut self.observer);
+        let mut best = None;
+        let mut offset = start;
+        while let Some((ch, len)) = char_at(self.text, offset, vm.byte_mode) {
+            if dfa.is_dead(state) {
+                scan_end = offset;
+                break;
+            }
+            self.observer.on_char_step(offset, ch, dfa.threads(state));
+            state = dfa.next_state(vm, state, ch, &mut self.observer);
+            offset += len;
+            if let Some(token_index) = dfa.accept(state) {
+                self.observer.on_match(token_index, offset);
+                best = Some(BestToken { token_index, end: offset });
+            }
+        }
+        (best, scan_end)
+    }
+
+    fn longest_match_simulated(&mut self, start: usize) -> (Option<BestToken>, usize) {
+        self.current_threads.clear();
+        self.visited.clear();
+        self.vm.add_thread(self.vm.mode_entry(self.mode()), &mut self.current_threads, &mut self.visited, &mut self.observer);
+        let mut best = None;
+        let mut scan_end = self.text.len() + 1;
+        let mut offset = start;
+        while let Some((ch, len)) = char_at(self.text, offset, self.vm.byte_mode) {
+            if self.current_threads.is_empty() {
+                scan_end = offset;
+                break;
+            }
+            self.observer.on_char_step(offset, ch, &self.current_threads);
+            let class = self.vm.classes.class_of(ch);
+            let match_res = self.vm.match_char(class, &self.current_threads, &mut self.next_threads, &mut self.advanced_threads, &mut self.visited, &mut self.observer);
+            offset += len;
+            if let Some(token_index) = match_res.max_matched_token_index {
+                self.observer.on_match(token_index, offset);
+                best = Some(BestToken { token_index, end: offset });
+            }
+            self.current_threads.clear();
+            swap(&mut self.current_threads, &mut self.next_threads);
         }
+        (best, scan_end)
     }
 
-    fn token_len(&self) -> u32 {
-        return self.position as u32 - self.token_start;
+    /// Offset after the char at `offset`
+    fn next_char_end(&self, offset: usize) -> usize {
+        offset + char_at(self.text, offset, self.vm.byte_mode).map(|(_, len)| len).unwrap_or(0)
     }
 }
 
-impl<'a, 'b> Iterator for LexingSession<'a, 'b> {
+/// Char at the offset and its length in bytes, in byte mode every byte is the char with the same code point.
+/// Otherwise the text is valid UTF-8, as it comes from `&str`
+#[inline]
+pub(crate) fn char_at(text: &[u8], offset: usize, byte_mode: bool) -> Option<(char, usize)> {
+    let first = *text.get(offset)?;
+    if first < 0x80 || byte_mode {
+        return Some((first as char, 1));
+    }
+    let len = if first >= 0xF0 { 4 } else if first >= 0xE0 { 3 } else { 2 };
+    let mut code_point = (first & (0x7F >> len)) as u32;
+    for &byte in &text[offset + 1..offset + len] {
+        code_point = (code_point << 6) | (byte & 0x3F) as u32;
+    }
+    Some((::std::char::from_u32(code_point).unwrap(), len))
+}
+
+impl<'a, 'b, O: VmObserver> Iterator for LexingSession<'a, 'b, O> {
     type Item = TokenRaw;
 
     fn next(&mut self) -> Option<<Self as Iterator>::Item> {
         if self.is_end {
             return Option::None;
         }
-        let mut best : Option<BestToken> = Option::None;
-        let mut error_mode = false;
-        self.vm.add_thread(0, false);
-        let (_, text) = self.text.split_at(self.position);
-        if self.position == self.text.len() {
+        let start = self.position;
+        if start == self.text.len() {
             self.is_end = true;
-            return Some(TokenRaw::new(0, END_TOKEN_INDEX));
+            self.scan_end = start + 1;
+            let token = TokenRaw::new(0, END_TOKEN_INDEX);
+            self.observer.on_token_emit(&token);
+            return Some(token);
         }
-        eprintln!("Lexing at position {:?}", self.position);
-        let mut result = Option::None;
-        for ch in text.chars() {
-            self.position += ch.len_utf8();
-            let pos = self.position;
-            eprintln!("ch = {:?}", ch);
-            eprintln!("self.current_threads = {:?}", self.vm.current_threads);
-            let match_res = self.vm.match_char(ch);
-            eprintln!("self.next = {:?} ", self.vm.next_threads);
-            // trying update BestToken
-            if let Some(new_max_index) = match_res.max_matched_token_index {
-                if error_mode {
-                    self.position -= ch.len_utf8();
-                    result = Option::Some(TokenRaw::new(self.token_len(), ERROR_TOKEN_INDEX));
-                    error_mode = false;
-                } else {
-                    if let Some(max_length_token) = best {
-                        if max_length_token.token_index <= new_max_index {
-                            best = Some(BestToken { token_index: new_max_index })
-                        }
-                    } else {
-                        best = Option::Some(BestToken { token_index: new_max_index });
-                    }
-                }
+        let (best, mut scan_end) = match self.lookahead.take() {
+            Some((best, scan_end)) => (Some(best), scan_end),
+            None => self.longest_match(start),
+        };
+        let token = match best {
+            Some(best) => {
+                self.position = best.end;
+                self.vm.apply_token_action(&mut self.mode_stack, best.token_index);
+                TokenRaw::new((best.end - start) as u32, best.token_index)
             }
-            if self.vm.next_threads.is_empty() {
-                eprintln!("Next threads are empty");
-                if !error_mode {
-                    if let Some(best_val) = best {
-                        result = Option::Some(TokenRaw::new(self.token_len(), best_val.token_index));
-                    } else {
-                        error_mode = true;
-                    };
+            None => {
+                // error token lasts until some token matches
+                let mut end = self.next_char_end(start);
+                while end < self.text.len() {
+                    let (best, next_scan_end) = self.longest_match(end);
+                    scan_end = max(scan_end, next_scan_end);
+                    if let Some(best) = best {
+                        self.lookahead = Some((best, next_scan_end));
+                        break;
+                    }
+                    end = self.next_char_end(end);
                 }
-                self.vm.add_thread(0, true);
-            }
-            self.vm.current_threads.clear();
-            swap(&mut self.vm.current_threads, &mut self.vm.next_threads);
-            if result.is_some() {
-                self.token_start = self.position as u32;
-                break;
+                self.position = end;
+                TokenRaw::new((end - start) as u32, ERROR_TOKEN_INDEX)
             }
-        }
-        if self.position != self.token_start as usize {
-
-            if let Some(best_val) = best {
-                result = Option::Some(TokenRaw::new(self.token_len(), best_val.token_index));
-            } else {
-                result = Option::Some(TokenRaw::new(self.token_len(), ERROR_TOKEN_INDEX))
-            };
-            let pos = self.position as u32;
-            let ts = self.token_start;
-            self.token_start = pos;
-        }
-        result
+        };
+        self.scan_end = scan_end;
+        self.observer.on_token_emit(&token);
+        Some(token)
     }
 }
 
 
-struct MatchResult {
-    max_matched_token_index: Option<u16>,
+pub(crate) struct MatchResult {
+    pub(crate) max_matched_token_index: Option<u16>,
 }
 
-const END_TOKEN_INDEX: u16 = 1;
-const ERROR_TOKEN_INDEX: u16 = 0;
+/// Token type index of the token, produced for the text, which is not matched by any token definition
+pub const ERROR_TOKEN_INDEX: u16 = 0;
+/// Token type index of the zero length token, produced at the end of the text
+pub const END_TOKEN_INDEX: u16 = 1;
 
 impl Vm {
-    /// handles all not immediately advancing instructions
-    fn add_thread(&mut self, pc: CodePointer, to_next: bool) -> Option<u16> {
+    /// handles all not immediately advancing instructions, only advancing ones are put to `threads`.
+    /// `visited` holds followed instructions, so that loops over empty expressions terminate,
+    /// it must be cleared together with `threads`
+    pub(crate) fn add_thread<O: VmObserver>(&self, pc: CodePointer, threads: &mut BitSet, visited: &mut BitSet, observer: &mut O) -> Option<u16> {
+        if !visited.insert(pc as usize) {
+            return None;
+        }
         let instruction = self.code[pc as usize];
-        return match decode(instruction) {
+        match decode(instruction) {
             Instruction::Split { then_instr_index, else_instr_index } => {
-                let left = self.add_thread(then_instr_index, to_next);
-                let right = self.add_thread(else_instr_index, to_next);
-                if let Some(left_val) = left {
-                    right.map(|right_val|std::cmp::max(left_val, right_val))
-                } else {
-                    right
+                let left = self.add_thread(then_instr_index, threads, visited, observer);
+                let right = self.add_thread(else_instr_index, threads, visited, observer);
+                self.best_token(left, right)
+            }
+            Instruction::SplitWide { table_index } => {
+                let table_index = table_index as usize;
+                let left = self.add_thread(self.constant_pool[table_index], threads, visited, observer);
+                let right = self.add_thread(self.constant_pool[table_index + 1], threads, visited, observer);
+                self.best_token(left, right)
+            }
+            Instruction::SplitMany { table_index } => {
+                let table_start = table_index as usize + 1;
+                let table_end = table_start + self.constant_pool[table_index as usize] as usize;
+                let mut result = None;
+                for target_index in table_start..table_end {
+                    result = self.best_token(result, self.add_thread(self.constant_pool[target_index], threads, visited, observer));
                 }
+                result
             }
-            Instruction::Jmp { instr_index } => self.add_thread(instr_index, to_next),
+            Instruction::Jmp { instr_index } => self.add_thread(instr_index, threads, visited, observer),
+            Instruction::Noop => self.add_thread(pc + 1, threads, visited, observer),
             Instruction::Match { token_type_index } => {
                 Some(token_type_index)
             }
+            Instruction::OuterLexer { .. } => None,
             _ => {
-                if to_next {
-                    self.next_threads.insert(pc as usize);
-                } else {
-                    self.current_threads.insert(pc as usize);
-                }
+                threads.insert(pc as usize);
+                observer.on_thread_add(pc);
                 None
             }
-        };
+        }
     }
 
-    fn match_char(&mut self, ch: char) -> MatchResult {
-        let mut all_failed = true;
-        let mut next: Option<CodePointer> = None;
-        for code_pointer in self.current_threads.iter() {
-            let instruction = self.code[code_pointer];
-            match decode(instruction) {
-                // must handle here only strictly advancing operations
-                Instruction::CharImm { ch: instr_ch } => {
-                    if instr_ch == ch {
-                        let new_code_pointer = (code_pointer + 1) as CodePointer;
-                        next = Some(new_code_pointer);
-                        all_failed = false;
-                    }
+    /// Advances threads, which match the char of the class, `next_threads` must be empty
+    pub(crate) fn match_char<O: VmObserver>(&self, class: usize, current_threads: &BitSet, next_threads: &mut BitSet, advanced: &mut Vec<CodePointer>, visited: &mut BitSet, observer: &mut O) -> MatchResult {
+        for code_pointer in current_threads.iter() {
+            if let Some(new_code_pointer) = self.step(code_pointer as CodePointer, class) {
+                advanced.push(new_code_pointer);
+            }
+        }
+        visited.clear();
+        let mut max_matched_token_index = None;
+        for new_code_pointer in advanced.drain(..) {
+            max_matched_token_index = self.best_token(max_matched_token_index, self.add_thread(new_code_pointer, next_threads, visited, observer));
+        }
+        MatchResult { max_matched_token_index }
+    }
+
+    /// Executes strictly advancing instruction, returns code pointer of the next instruction, if char of the class matches
+    fn step(&self, code_pointer: CodePointer, class: usize) -> Option<CodePointer> {
+        match decode(self.code[code_pointer as usize]) {
+            Instruction::Invert { count } => {
+                let first_member = code_pointer as usize + 1;
+                let after_members = first_member + count as usize;
+                if self.class_sets[first_member..after_members].iter().any(|members| members.contains(class)) {
+                    None
+                } else {
+                    Some(after_members as CodePointer)
                 }
-                Instruction::RangeImm { from, to } => {
-                    if ch >= from && ch <= to {
-                        let new_code_pointer = (code_pointer + 1) as CodePointer;
-                        next = Some(new_code_pointer);
-                        all_failed = false;
-                    }
+            }
+            _ => {
+                if self.class_sets[code_pointer as usize].contains(class) {
+                    Some(code_pointer + 1)
+                } else {
+                    None
                 }
-
-                _ => {}
             }
         }
-        let max_matched_token_index = match next {
-            Some(cp) => self.add_thread(cp, true),
-            None => None
-        };
-        return MatchResult { max_matched_token_index };
     }
 }
 
 
-#[derive(Debug, PartialEq, Eq)]
+/// Compact token: length in bytes and index of the token type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TokenRaw {
     length: u32,
     token_type_index: u16,
@@ -221,13 +602,13 @@ impl TokenRaw {
     pub fn new(length: u32, token_type_index: u16) -> Self {
         TokenRaw { length, token_type_index }
     }
-}
 
-impl Iterator for Vm {
-    type Item = TokenRaw;
+    pub fn length(&self) -> u32 {
+        self.length
+    }
 
-    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-        Option::None
+    pub fn token_type_index(&self) -> u16 {
+        self.token_type_index
     }
 }
 
@@ -236,13 +617,16 @@ mod tests {
     use super::*;
     use assembler::Assembler;
     use assembler::ProgramData;
+    use ast::LexerDefinition;
+    use ast::TokenDefinition;
+    use compiler::Compiler;
 
     #[test]
     fn single_char() {
         let mut asm = Assembler::new();
         asm.emit_char_imm('a');
         asm.emit_match(2);
-        test_vm(asm.finish(), "a", vec![
+        test_vm(asm.finish().unwrap(), "a", vec![
             TokenRaw::new(1, 2),
             TokenRaw::new(0, END_TOKEN_INDEX)
         ])
@@ -254,7 +638,7 @@ mod tests {
         let mut asm = Assembler::new();
         asm.emit_range_imm('a', 'z');
         asm.emit_match(2);
-        test_vm(asm.finish(), "v", vec![
+        test_vm(asm.finish().unwrap(), "v", vec![
             TokenRaw::new(1, 2),
             TokenRaw::new(0, END_TOKEN_INDEX)
         ])
@@ -266,7 +650,7 @@ mod tests {
         asm.emit_char_imm('a');
         asm.emit_char_imm('a');
         asm.emit_match(2);
-        test_vm(asm.finish(), "aa", vec![
+        test_vm(asm.finish().unwrap(), "aa", vec![
             TokenRaw::new(2, 2),
             TokenRaw::new(0, END_TOKEN_INDEX)
         ])
@@ -278,7 +662,7 @@ mod tests {
         asm.emit_char_imm('a');
         asm.emit_split(0, 2);
         asm.emit_match(2);
-        test_vm(asm.finish(), "aaaa", vec![
+        test_vm(asm.finish().unwrap(), "aaaa", vec![
             TokenRaw::new(4, 2),
             TokenRaw::new(0, END_TOKEN_INDEX)
         ])
@@ -293,7 +677,7 @@ mod tests {
         asm.emit_match(2);
         asm.emit_char_imm('b');
         asm.emit_match(3);
-        test_vm(asm.finish(), "ab", vec![
+        test_vm(asm.finish().unwrap(), "ab", vec![
             TokenRaw::new(1, 2),
             TokenRaw::new(1, 3),
             TokenRaw::new(0, END_TOKEN_INDEX)
@@ -306,7 +690,7 @@ mod tests {
         // a | b regex code
         asm.emit_char_imm('a');
         asm.emit_match(2);
-        test_vm(asm.finish(), "abbbaa", vec![
+        test_vm(asm.finish().unwrap(), "abbbaa", vec![
             TokenRaw::new(1, 2),
             TokenRaw::new(3, 0),
             TokenRaw::new(1, 2),
@@ -315,10 +699,313 @@ mod tests {
         ])
     }
 
+    #[test]
+    fn lex_common_prefix() {
+        let mut asm = Assembler::new();
+        // ab | ac regex code
+        asm.emit_split(1, 4);
+        asm.emit_char_imm('a');
+        asm.emit_char_imm('b');
+        asm.emit_match(2);
+        asm.emit_char_imm('a');
+        asm.emit_char_imm('c');
+        asm.emit_match(3);
+        test_vm(asm.finish().unwrap(), "abac", vec![
+            TokenRaw::new(2, 2),
+            TokenRaw::new(2, 3),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ])
+    }
+
+    #[test]
+    fn lex_negated_class() {
+        let mut asm = Assembler::new();
+        // "[^"a-z]*" regex code
+        asm.emit_char_imm('"');
+        asm.emit_split(2, 6);
+        asm.emit_invert(2);
+        asm.emit_char_imm('"');
+        asm.emit_range_imm('a', 'z');
+        asm.emit_jmp(1);
+        asm.emit_char_imm('"');
+        asm.emit_match(2);
+        test_vm(asm.finish().unwrap(), "\"A1\"\"\"\"\u{3b1}\"", vec![
+            TokenRaw::new(4, 2),
+            TokenRaw::new(2, 2),
+            TokenRaw::new(4, 2),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ])
+    }
+
+    #[test]
+    fn lex_line_comment() {
+        let mut asm = Assembler::new();
+        // //.* | \n regex code
+        asm.emit_split(1, 7);
+        asm.emit_char_imm('/');
+        asm.emit_char_imm('/');
+        asm.emit_split(4, 6);
+        asm.emit_any(false);
+        asm.emit_jmp(3);
+        asm.emit_match(2);
+        asm.emit_char_imm('\n');
+        asm.emit_match(3);
+        test_vm(asm.finish().unwrap(), "\n// \u{3b1}", vec![
+            TokenRaw::new(1, 3),
+            TokenRaw::new(5, 2),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ])
+    }
+
+    #[test]
+    fn lex_pool_range() {
+        let mut asm = Assembler::new();
+        // #[\u{4E00}-\u{9FFF}\u{1F600}-\u{1F64F}]+ regex code
+        asm.emit_char_cp('#');
+        asm.emit_split(2, 4);
+        asm.emit_range_cp('\u{4E00}', '\u{9FFF}');
+        asm.emit_jmp(5);
+        asm.emit_range_cp('\u{1F600}', '\u{1F64F}');
+        asm.emit_split(1, 6);
+        asm.emit_match(2);
+        test_vm(asm.finish().unwrap(), "#\u{4E2D}\u{1F600}\u{6587}", vec![
+            TokenRaw::new(11, 2),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ])
+    }
+
+    #[test]
+    fn lex_split_many() {
+        let mut asm = Assembler::new();
+        // a | b | c regex code
+        let marker = asm.emit_split_many();
+        asm.emit_char_imm('a');
+        asm.emit_match(2);
+        asm.emit_char_imm('b');
+        asm.emit_match(3);
+        asm.emit_char_imm('c');
+        asm.emit_match(4);
+        asm.patch_split_many(&marker, vec![1, 3, 5]);
+        test_vm(asm.finish().unwrap(), "cab", vec![
+            TokenRaw::new(1, 4),
+            TokenRaw::new(1, 2),
+            TokenRaw::new(1, 3),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ])
+    }
+
+    #[test]
+    fn lex_wide_split() {
+        let mut asm = Assembler::new();
+        // a | b regex code, where b is too far for Split
+        let far = 1 << 15;
+        asm.emit_split(1, far);
+        asm.emit_char_imm('a');
+        asm.emit_match(2);
+        while asm.next_code_position() < far {
+            asm.emit_noop();
+        }
+        asm.emit_char_imm('b');
+        asm.emit_match(3);
+        test_vm(asm.finish().unwrap(), "ba", vec![
+            TokenRaw::new(1, 3),
+            TokenRaw::new(1, 2),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ])
+    }
+
+    #[test]
+    fn lex_rewind_to_longest_match() {
+        let mut asm = Assembler::new();
+        // = | ==> regex code
+        asm.emit_split(1, 3);
+        asm.emit_char_imm('=');
+        asm.emit_match(2);
+        asm.emit_char_imm('=');
+        asm.emit_char_imm('=');
+        asm.emit_char_imm('>');
+        asm.emit_match(3);
+        test_vm(asm.finish().unwrap(), "===>==", vec![
+            TokenRaw::new(1, 2),
+            TokenRaw::new(3, 3),
+            TokenRaw::new(1, 2),
+            TokenRaw::new(1, 2),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ])
+    }
+
+    #[test]
+    fn lex_loop_over_empty() {
+        let mut asm = Assembler::new();
+        // (a?)* regex code
+        asm.emit_split(1, 4);
+        asm.emit_split(2, 3);
+        asm.emit_char_imm('a');
+        asm.emit_split(1, 4);
+        asm.emit_match(2);
+        test_vm(asm.finish().unwrap(), "aab", vec![
+            TokenRaw::new(2, 2),
+            TokenRaw::new(1, ERROR_TOKEN_INDEX),
+            TokenRaw::new(0, END_TOKEN_INDEX)
+        ])
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        thread_adds: usize,
+        chars: Vec<char>,
+        matches: Vec<(u16, usize)>,
+        tokens: Vec<TokenRaw>,
+    }
+
+    impl VmObserver for RecordingObserver {
+        fn on_thread_add(&mut self, _code_pointer: CodePointer) {
+            self.thread_adds += 1;
+        }
+
+        fn on_char_step(&mut self, _offset: usize, ch: char, _threads: &BitSet) {
+            self.chars.push(ch);
+        }
+
+        fn on_match(&mut self, token_type_index: u16, end: usize) {
+            self.matches.push((token_type_index, end));
+        }
+
+        fn on_token_emit(&mut self, token: &TokenRaw) {
+            self.tokens.push(*token);
+        }
+    }
+
+    #[test]
+    fn observer() {
+        let mut asm = Assembler::new();
+        asm.emit_char_imm('a');
+        asm.emit_split(0, 2);
+        asm.emit_match(2);
+        let program_data = asm.finish().unwrap();
+        let vm = Vm::new(program_data.code, program_data.constant_pool);
+        let mut session = vm.tokenize_observed("aab", RecordingObserver::default());
+        let tokens: Vec<TokenRaw> = session.by_ref().collect();
+        let observer = session.into_observer();
+        assert_eq!(tokens, observer.tokens);
+        assert_eq!(vec!['a', 'a', 'b', 'b'], observer.chars);
+        assert_eq!(vec![(2, 1), (2, 2)], observer.matches);
+        assert!(observer.thread_adds > 0);
+        // cached states are not recomputed
+        let vm = vm.with_dfa_cache_size(0);
+        let mut session = vm.tokenize_observed("aab", RecordingObserver::default());
+        session.by_ref().for_each(drop);
+        assert!(session.into_observer().thread_adds > observer.thread_adds);
+    }
+
+    #[test]
+    fn lex_thrashing_dfa_cache() {
+        let mut asm = Assembler::new();
+        // [a-z]+ | [0-9]+ regex code
+        asm.emit_split(1, 5);
+        asm.emit_range_imm('a', 'z');
+        asm.emit_split(1, 3);
+        asm.emit_match(2);
+        asm.emit_noop();
+        asm.emit_range_imm('0', '9');
+        asm.emit_split(5, 7);
+        asm.emit_match(3);
+        let program_data = asm.finish().unwrap();
+        let vm = Vm::new(program_data.code, program_data.constant_pool).with_dfa_cache_size(2);
+        let text = "ab12cd34".repeat(10);
+        let mut session = vm.tokenize(&text);
+        assert!(session.uses_dfa_cache());
+        let tokens: Vec<TokenRaw> = session.by_ref().collect();
+        assert!(!session.uses_dfa_cache());
+        assert_eq!(41, tokens.len());
+        assert!(tokens[..40].iter().all(|token| token.length() == 2));
+    }
+
+    /// Checks tokens both with DFA cache and with threads simulation only
+    #[test]
+    fn lex_bytes() {
+        let definition = LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "WORD", "[a-z\u{3b1}-\u{3c9}]+").unwrap(),
+            TokenDefinition::from_pattern(3, "WS", " +").unwrap(),
+            TokenDefinition::from_pattern(4, "STRING", "\"[^\"]*\"").unwrap(),
+            TokenDefinition::from_pattern(5, "LINE", "#.*").unwrap(),
+        ]);
+        let char_vm = Compiler::new().compile_lexer(&definition).unwrap();
+        let byte_vm = Compiler::new().with_byte_mode(true).compile_lexer(&definition).unwrap();
+        let text = "ab \u{3b1}\u{3b2} \"\u{1F600}\u{3b1}\" ?\u{E9}? #\u{10FFFF} x\nz";
+        let expected: Vec<TokenRaw> = char_vm.tokenize(text).collect();
+        for &dfa_cache_size in &[DEFAULT_DFA_CACHE_SIZE, 0] {
+            let byte_vm = Compiler::new().with_byte_mode(true).compile_lexer(&definition).unwrap().with_dfa_cache_size(dfa_cache_size);
+            assert_eq!(expected, byte_vm.tokenize_bytes(text.as_bytes()).unwrap().collect::<Vec<TokenRaw>>());
+        }
+        // Latin-1 and truncated UTF-8 are error tokens
+        let tokens: Vec<TokenRaw> = byte_vm.tokenize_bytes(b"caf\xE9 \"\xCE\" \xCE\xB1\xFF").unwrap().collect();
+        assert_eq!(vec![
+            TokenRaw::new(3, 2),
+            TokenRaw::new(1, ERROR_TOKEN_INDEX),
+            TokenRaw::new(1, 3),
+            TokenRaw::new(3, ERROR_TOKEN_INDEX),
+            TokenRaw::new(1, 3),
+            TokenRaw::new(2, 2),
+            TokenRaw::new(1, ERROR_TOKEN_INDEX),
+            TokenRaw::new(0, END_TOKEN_INDEX),
+        ], tokens);
+        // program compiled for chars decodes UTF-8
+        assert_eq!(expected, char_vm.tokenize_bytes(text.as_bytes()).unwrap().collect::<Vec<TokenRaw>>());
+        assert!(char_vm.tokenize_bytes(b"caf\xE9").is_err());
+    }
+
+    #[test]
+    fn restart_resets_scan_end() {
+        let vm = Compiler::new().compile_lexer(&LexerDefinition::new(vec![
+            TokenDefinition::from_pattern(2, "WORD", "[a-z]+").unwrap()
+        ])).unwrap();
+        let mut session = vm.tokenize("abc");
+        assert_eq!(Some(TokenRaw::new(3, 2)), session.next());
+        assert_eq!(4, session.scan_end());
+        session.restart("ab cd", 3, &[0]);
+        assert_eq!(3, session.scan_end());
+        assert_eq!(Some(TokenRaw::new(2, 2)), session.next());
+        assert_eq!(6, session.scan_end());
+    }
+
+    #[test]
+    fn lex_compiled_alternation() {
+        // variant of alternation or class must not fall through into the following ones
+        for pattern in &["(a|b)c", "[ab]c", "(a|b|d)c"] {
+            let mut compiler = Compiler::new();
+            compiler.generate_lexer(&LexerDefinition::new(vec![
+                TokenDefinition::from_pattern(2, "foo", pattern).unwrap()
+            ])).unwrap();
+            test_vm(compiler.get_prog_data().unwrap(), "acbc", vec![
+                TokenRaw::new(2, 2),
+                TokenRaw::new(2, 2),
+                TokenRaw::new(0, END_TOKEN_INDEX)
+            ])
+        }
+    }
+
+    #[test]
+    fn lex_compiled_empty_loop() {
+        // loop over expression, which matches nothing, must not revisit its split forever
+        for pattern in &["(a?)*", "(a*)*", "()*a*"] {
+            let mut compiler = Compiler::new();
+            compiler.generate_lexer(&LexerDefinition::new(vec![
+                TokenDefinition::from_pattern(2, "foo", pattern).unwrap()
+            ])).unwrap();
+            test_vm(compiler.get_prog_data().unwrap(), "aa", vec![
+                TokenRaw::new(2, 2),
+                TokenRaw::new(0, END_TOKEN_INDEX)
+            ])
+        }
+    }
+
     fn test_vm(program_data: ProgramData, text: &str, expected_tokens: Vec<TokenRaw>) {
-        let mut vm = Vm::new(program_data.code, program_data.constant_pool);
-        let tokens: Vec<TokenRaw> = LexingSession::new(&mut vm, text).collect();
-//        let tokens = vm.tokenize(text);
+        let vm = Vm::new(program_data.code, program_data.constant_pool);
+        let tokens: Vec<TokenRaw> = vm.tokenize(text).collect();
+        assert_eq!(expected_tokens, tokens);
+        let vm = vm.with_dfa_cache_size(0);
+        let tokens: Vec<TokenRaw> = vm.tokenize(text).collect();
         assert_eq!(expected_tokens, tokens);
     }
 }
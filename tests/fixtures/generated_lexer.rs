@@ -0,0 +1,206 @@
+// Generated by lexer-vm, do not edit
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+#[allow(dead_code)]
+pub enum TokenType {
+    Error = 0,
+    End = 1,
+    If = 2,
+    Ident = 3,
+    Number = 4,
+    String = 5,
+    Ws = 6,
+    LineComment = 7,
+    Arrow = 8,
+    Minus = 9,
+    Slash = 10,
+}
+
+#[allow(dead_code)]
+impl TokenType {
+    /// Token type by token type index, None if there is no such token type
+    pub fn from_index(index: u16) -> Option<TokenType> {
+        match index {
+            0 => Some(TokenType::Error),
+            1 => Some(TokenType::End),
+            2 => Some(TokenType::If),
+            3 => Some(TokenType::Ident),
+            4 => Some(TokenType::Number),
+            5 => Some(TokenType::String),
+            6 => Some(TokenType::Ws),
+            7 => Some(TokenType::LineComment),
+            8 => Some(TokenType::Arrow),
+            9 => Some(TokenType::Minus),
+            10 => Some(TokenType::Slash),
+            _ => None,
+        }
+    }
+
+    pub fn index(self) -> u16 {
+        self as u16
+    }
+
+    /// Name of the token type in the lexer definition
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenType::Error => "ERROR",
+            TokenType::End => "END",
+            TokenType::If => "IF",
+            TokenType::Ident => "IDENT",
+            TokenType::Number => "NUMBER",
+            TokenType::String => "STRING",
+            TokenType::Ws => "WS",
+            TokenType::LineComment => "LINE_COMMENT",
+            TokenType::Arrow => "ARROW",
+            TokenType::Minus => "MINUS",
+            TokenType::Slash => "SLASH",
+        }
+    }
+}
+
+impl ::std::fmt::Display for TokenType {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Token type and byte offsets of the token in the text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub token_type: TokenType,
+    pub start: usize,
+    pub end: usize,
+}
+
+const DEAD_STATE: usize = 0;
+const START_STATE: usize = 1;
+const CLASS_COUNT: usize = 29;
+static CLASS_STARTS: [u32; 29] = [
+    0, 9, 10, 11, 32, 33, 34, 35, 45, 46, 47, 48, 58, 62, 63, 65,
+    91, 92, 93, 95, 96, 97, 102, 103, 105, 106, 123, 945, 970,
+];
+static ASCII_CLASSES: [u8; 128] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    4, 5, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 8, 9, 10,
+    11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 12, 12, 12, 12, 13, 14,
+    14, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 16, 17, 18, 18, 19,
+    20, 21, 21, 21, 21, 21, 22, 23, 23, 24, 25, 25, 25, 25, 25, 25,
+    25, 25, 25, 25, 25, 25, 25, 25, 25, 25, 25, 26, 26, 26, 26, 26,
+];
+static TRANSITIONS: [u8; 464] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 2,
+    0, 2, 0, 3, 0, 4, 0, 5, 6, 0, 0, 0, 7, 0, 0, 0,
+    7, 0, 7, 7, 7, 8, 7, 0, 7, 0, 0, 2, 2, 0, 2, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 3, 3, 3, 3, 3, 3, 9, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 10, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 13, 0, 6, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 7, 0, 0, 0, 7, 0,
+    7, 7, 7, 7, 7, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 7, 0, 0, 0, 7, 0, 0, 0, 7, 0, 7, 14, 7,
+    7, 7, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 3, 3, 0, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 12, 12, 0, 12,
+    12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12,
+    12, 12, 12, 12, 12, 12, 12, 12, 12, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 7, 0, 0, 0, 7, 0, 0, 0, 7, 0, 7, 7, 7, 7, 7,
+    0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 15, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+static ACCEPTS: [Option<TokenType>; 16] = [
+    None, None, Some(TokenType::Ws), None, Some(TokenType::Minus), Some(TokenType::Slash), Some(TokenType::Number), Some(TokenType::Ident), Some(TokenType::Ident), Some(TokenType::String), None, Some(TokenType::Arrow), Some(TokenType::LineComment), None, Some(TokenType::If), Some(TokenType::Number),
+];
+
+fn class_of(ch: char) -> usize {
+    let code_point = ch as u32;
+    if code_point < 128 {
+        return ASCII_CLASSES[code_point as usize] as usize;
+    }
+    match CLASS_STARTS.binary_search(&code_point) {
+        Ok(class) => class,
+        Err(next_class) => next_class - 1,
+    }
+}
+
+/// Longest match at `start`: token type and end
+fn longest_match(text: &str, start: usize) -> Option<(TokenType, usize)> {
+    let mut state = START_STATE;
+    let mut best = None;
+    for (offset, ch) in text[start..].char_indices() {
+        state = TRANSITIONS[state * CLASS_COUNT + class_of(ch)] as usize;
+        if state == DEAD_STATE {
+            break;
+        }
+        if let Some(token_type) = ACCEPTS[state] {
+            best = Some((token_type, start + offset + ch.len_utf8()));
+        }
+    }
+    best
+}
+
+/// Lazily splits text into tokens, finishing with zero length `TokenType::End` token.
+/// Text, which doesn't match any token, is reported as `TokenType::Error` tokens
+pub fn tokenize<'a>(text: &'a str) -> Lexer<'a> {
+    Lexer { text, position: 0, is_end: false, lookahead: None }
+}
+
+pub struct Lexer<'a> {
+    text: &'a str,
+    position: usize,
+    is_end: bool,
+    lookahead: Option<(TokenType, usize)>,
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.is_end {
+            return None;
+        }
+        let start = self.position;
+        if start == self.text.len() {
+            self.is_end = true;
+            return Some(Token { token_type: TokenType::End, start, end: start });
+        }
+        let best = match self.lookahead.take() {
+            Some(best) => Some(best),
+            None => longest_match(self.text, start),
+        };
+        let (token_type, end) = match best {
+            Some(best) => best,
+            None => {
+                // error token lasts until some token matches
+                let mut end = next_char_end(self.text, start);
+                while end < self.text.len() {
+                    if let Some(best) = longest_match(self.text, end) {
+                        self.lookahead = Some(best);
+                        break;
+                    }
+                    end = next_char_end(self.text, end);
+                }
+                (TokenType::Error, end)
+            }
+        };
+        self.position = end;
+        Some(Token { token_type, start, end })
+    }
+}
+
+fn next_char_end(text: &str, offset: usize) -> usize {
+    offset + text[offset..].chars().next().map(|ch| ch.len_utf8()).unwrap_or(0)
+}
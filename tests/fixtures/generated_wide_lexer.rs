@@ -0,0 +1,271 @@
+// Generated by lexer-vm, do not edit
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+#[allow(dead_code)]
+pub enum TokenType {
+    Error = 0,
+    End = 1,
+    Word = 2,
+    LongWord = 3,
+    Ws = 4,
+}
+
+#[allow(dead_code)]
+impl TokenType {
+    /// Token type by token type index, None if there is no such token type
+    pub fn from_index(index: u16) -> Option<TokenType> {
+        match index {
+            0 => Some(TokenType::Error),
+            1 => Some(TokenType::End),
+            2 => Some(TokenType::Word),
+            3 => Some(TokenType::LongWord),
+            4 => Some(TokenType::Ws),
+            _ => None,
+        }
+    }
+
+    pub fn index(self) -> u16 {
+        self as u16
+    }
+
+    /// Name of the token type in the lexer definition
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenType::Error => "ERROR",
+            TokenType::End => "END",
+            TokenType::Word => "WORD",
+            TokenType::LongWord => "LONG_WORD",
+            TokenType::Ws => "WS",
+        }
+    }
+}
+
+impl ::std::fmt::Display for TokenType {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Token type and byte offsets of the token in the text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub token_type: TokenType,
+    pub start: usize,
+    pub end: usize,
+}
+
+const DEAD_STATE: usize = 0;
+const START_STATE: usize = 1;
+const CLASS_COUNT: usize = 5;
+static CLASS_STARTS: [u32; 5] = [
+    0, 32, 33, 97, 98,
+];
+static ASCII_CLASSES: [u8; 128] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 3, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+];
+static TRANSITIONS: [u16; 1520] = [
+    0, 0, 0, 0, 0, 0, 2, 0, 3, 0, 0, 2, 0, 0, 0, 0,
+    0, 0, 4, 0, 0, 0, 0, 5, 0, 0, 0, 0, 6, 0, 0, 0,
+    0, 7, 0, 0, 0, 0, 8, 0, 0, 0, 0, 9, 0, 0, 0, 0,
+    10, 0, 0, 0, 0, 11, 0, 0, 0, 0, 12, 0, 0, 0, 0, 13,
+    0, 0, 0, 0, 14, 0, 0, 0, 0, 15, 0, 0, 0, 0, 16, 0,
+    0, 0, 0, 17, 0, 0, 0, 0, 18, 0, 0, 0, 0, 19, 0, 0,
+    0, 0, 20, 0, 0, 0, 0, 21, 0, 0, 0, 0, 22, 0, 0, 0,
+    0, 23, 0, 0, 0, 0, 24, 0, 0, 0, 0, 25, 0, 0, 0, 0,
+    26, 0, 0, 0, 0, 27, 0, 0, 0, 0, 28, 0, 0, 0, 0, 29,
+    0, 0, 0, 0, 30, 0, 0, 0, 0, 31, 0, 0, 0, 0, 32, 0,
+    0, 0, 0, 33, 0, 0, 0, 0, 34, 0, 0, 0, 0, 35, 0, 0,
+    0, 0, 36, 0, 0, 0, 0, 37, 0, 0, 0, 0, 38, 0, 0, 0,
+    0, 39, 0, 0, 0, 0, 40, 0, 0, 0, 0, 41, 0, 0, 0, 0,
+    42, 0, 0, 0, 0, 43, 0, 0, 0, 0, 44, 0, 0, 0, 0, 45,
+    0, 0, 0, 0, 46, 0, 0, 0, 0, 47, 0, 0, 0, 0, 48, 0,
+    0, 0, 0, 49, 0, 0, 0, 0, 50, 0, 0, 0, 0, 51, 0, 0,
+    0, 0, 52, 0, 0, 0, 0, 53, 0, 0, 0, 0, 54, 0, 0, 0,
+    0, 55, 0, 0, 0, 0, 56, 0, 0, 0, 0, 57, 0, 0, 0, 0,
+    58, 0, 0, 0, 0, 59, 0, 0, 0, 0, 60, 0, 0, 0, 0, 61,
+    0, 0, 0, 0, 62, 0, 0, 0, 0, 63, 0, 0, 0, 0, 64, 0,
+    0, 0, 0, 65, 0, 0, 0, 0, 66, 0, 0, 0, 0, 67, 0, 0,
+    0, 0, 68, 0, 0, 0, 0, 69, 0, 0, 0, 0, 70, 0, 0, 0,
+    0, 71, 0, 0, 0, 0, 72, 0, 0, 0, 0, 73, 0, 0, 0, 0,
+    74, 0, 0, 0, 0, 75, 0, 0, 0, 0, 76, 0, 0, 0, 0, 77,
+    0, 0, 0, 0, 78, 0, 0, 0, 0, 79, 0, 0, 0, 0, 80, 0,
+    0, 0, 0, 81, 0, 0, 0, 0, 82, 0, 0, 0, 0, 83, 0, 0,
+    0, 0, 84, 0, 0, 0, 0, 85, 0, 0, 0, 0, 86, 0, 0, 0,
+    0, 87, 0, 0, 0, 0, 88, 0, 0, 0, 0, 89, 0, 0, 0, 0,
+    90, 0, 0, 0, 0, 91, 0, 0, 0, 0, 92, 0, 0, 0, 0, 93,
+    0, 0, 0, 0, 94, 0, 0, 0, 0, 95, 0, 0, 0, 0, 96, 0,
+    0, 0, 0, 97, 0, 0, 0, 0, 98, 0, 0, 0, 0, 99, 0, 0,
+    0, 0, 100, 0, 0, 0, 0, 101, 0, 0, 0, 0, 102, 0, 0, 0,
+    0, 103, 0, 0, 0, 0, 104, 0, 0, 0, 0, 105, 0, 0, 0, 0,
+    106, 0, 0, 0, 0, 107, 0, 0, 0, 0, 108, 0, 0, 0, 0, 109,
+    0, 0, 0, 0, 110, 0, 0, 0, 0, 111, 0, 0, 0, 0, 112, 0,
+    0, 0, 0, 113, 0, 0, 0, 0, 114, 0, 0, 0, 0, 115, 0, 0,
+    0, 0, 116, 0, 0, 0, 0, 117, 0, 0, 0, 0, 118, 0, 0, 0,
+    0, 119, 0, 0, 0, 0, 120, 0, 0, 0, 0, 121, 0, 0, 0, 0,
+    122, 0, 0, 0, 0, 123, 0, 0, 0, 0, 124, 0, 0, 0, 0, 125,
+    0, 0, 0, 0, 126, 0, 0, 0, 0, 127, 0, 0, 0, 0, 128, 0,
+    0, 0, 0, 129, 0, 0, 0, 0, 130, 0, 0, 0, 0, 131, 0, 0,
+    0, 0, 132, 0, 0, 0, 0, 133, 0, 0, 0, 0, 134, 0, 0, 0,
+    0, 135, 0, 0, 0, 0, 136, 0, 0, 0, 0, 137, 0, 0, 0, 0,
+    138, 0, 0, 0, 0, 139, 0, 0, 0, 0, 140, 0, 0, 0, 0, 141,
+    0, 0, 0, 0, 142, 0, 0, 0, 0, 143, 0, 0, 0, 0, 144, 0,
+    0, 0, 0, 145, 0, 0, 0, 0, 146, 0, 0, 0, 0, 147, 0, 0,
+    0, 0, 148, 0, 0, 0, 0, 149, 0, 0, 0, 0, 150, 0, 0, 0,
+    0, 151, 0, 0, 0, 0, 152, 0, 0, 0, 0, 153, 0, 0, 0, 0,
+    154, 0, 0, 0, 0, 155, 0, 0, 0, 0, 156, 0, 0, 0, 0, 157,
+    0, 0, 0, 0, 158, 0, 0, 0, 0, 159, 0, 0, 0, 0, 160, 0,
+    0, 0, 0, 161, 0, 0, 0, 0, 162, 0, 0, 0, 0, 163, 0, 0,
+    0, 0, 164, 0, 0, 0, 0, 165, 0, 0, 0, 0, 166, 0, 0, 0,
+    0, 167, 0, 0, 0, 0, 168, 0, 0, 0, 0, 169, 0, 0, 0, 0,
+    170, 0, 0, 0, 0, 171, 0, 0, 0, 0, 172, 0, 0, 0, 0, 173,
+    0, 0, 0, 0, 174, 0, 0, 0, 0, 175, 0, 0, 0, 0, 176, 0,
+    0, 0, 0, 177, 0, 0, 0, 0, 178, 0, 0, 0, 0, 179, 0, 0,
+    0, 0, 180, 0, 0, 0, 0, 181, 0, 0, 0, 0, 182, 0, 0, 0,
+    0, 183, 0, 0, 0, 0, 184, 0, 0, 0, 0, 185, 0, 0, 0, 0,
+    186, 0, 0, 0, 0, 187, 0, 0, 0, 0, 188, 0, 0, 0, 0, 189,
+    0, 0, 0, 0, 190, 0, 0, 0, 0, 191, 0, 0, 0, 0, 192, 0,
+    0, 0, 0, 193, 0, 0, 0, 0, 194, 0, 0, 0, 0, 195, 0, 0,
+    0, 0, 196, 0, 0, 0, 0, 197, 0, 0, 0, 0, 198, 0, 0, 0,
+    0, 199, 0, 0, 0, 0, 200, 0, 0, 0, 0, 201, 0, 0, 0, 0,
+    202, 0, 0, 0, 0, 203, 0, 0, 0, 0, 204, 0, 0, 0, 0, 205,
+    0, 0, 0, 0, 206, 0, 0, 0, 0, 207, 0, 0, 0, 0, 208, 0,
+    0, 0, 0, 209, 0, 0, 0, 0, 210, 0, 0, 0, 0, 211, 0, 0,
+    0, 0, 212, 0, 0, 0, 0, 213, 0, 0, 0, 0, 214, 0, 0, 0,
+    0, 215, 0, 0, 0, 0, 216, 0, 0, 0, 0, 217, 0, 0, 0, 0,
+    218, 0, 0, 0, 0, 219, 0, 0, 0, 0, 220, 0, 0, 0, 0, 221,
+    0, 0, 0, 0, 222, 0, 0, 0, 0, 223, 0, 0, 0, 0, 224, 0,
+    0, 0, 0, 225, 0, 0, 0, 0, 226, 0, 0, 0, 0, 227, 0, 0,
+    0, 0, 228, 0, 0, 0, 0, 229, 0, 0, 0, 0, 230, 0, 0, 0,
+    0, 231, 0, 0, 0, 0, 232, 0, 0, 0, 0, 233, 0, 0, 0, 0,
+    234, 0, 0, 0, 0, 235, 0, 0, 0, 0, 236, 0, 0, 0, 0, 237,
+    0, 0, 0, 0, 238, 0, 0, 0, 0, 239, 0, 0, 0, 0, 240, 0,
+    0, 0, 0, 241, 0, 0, 0, 0, 242, 0, 0, 0, 0, 243, 0, 0,
+    0, 0, 244, 0, 0, 0, 0, 245, 0, 0, 0, 0, 246, 0, 0, 0,
+    0, 247, 0, 0, 0, 0, 248, 0, 0, 0, 0, 249, 0, 0, 0, 0,
+    250, 0, 0, 0, 0, 251, 0, 0, 0, 0, 252, 0, 0, 0, 0, 253,
+    0, 0, 0, 0, 254, 0, 0, 0, 0, 255, 0, 0, 0, 0, 256, 0,
+    0, 0, 0, 257, 0, 0, 0, 0, 258, 0, 0, 0, 0, 259, 0, 0,
+    0, 0, 260, 0, 0, 0, 0, 261, 0, 0, 0, 0, 262, 0, 0, 0,
+    0, 263, 0, 0, 0, 0, 264, 0, 0, 0, 0, 265, 0, 0, 0, 0,
+    266, 0, 0, 0, 0, 267, 0, 0, 0, 0, 268, 0, 0, 0, 0, 269,
+    0, 0, 0, 0, 270, 0, 0, 0, 0, 271, 0, 0, 0, 0, 272, 0,
+    0, 0, 0, 273, 0, 0, 0, 0, 274, 0, 0, 0, 0, 275, 0, 0,
+    0, 0, 276, 0, 0, 0, 0, 277, 0, 0, 0, 0, 278, 0, 0, 0,
+    0, 279, 0, 0, 0, 0, 280, 0, 0, 0, 0, 281, 0, 0, 0, 0,
+    282, 0, 0, 0, 0, 283, 0, 0, 0, 0, 284, 0, 0, 0, 0, 285,
+    0, 0, 0, 0, 286, 0, 0, 0, 0, 287, 0, 0, 0, 0, 288, 0,
+    0, 0, 0, 289, 0, 0, 0, 0, 290, 0, 0, 0, 0, 291, 0, 0,
+    0, 0, 292, 0, 0, 0, 0, 293, 0, 0, 0, 0, 294, 0, 0, 0,
+    0, 295, 0, 0, 0, 0, 296, 0, 0, 0, 0, 297, 0, 0, 0, 0,
+    298, 0, 0, 0, 0, 299, 0, 0, 0, 0, 300, 0, 0, 0, 0, 301,
+    0, 0, 0, 0, 302, 0, 0, 0, 0, 303, 0, 0, 0, 0, 303, 0,
+];
+static ACCEPTS: [Option<TokenType>; 304] = [
+    None, None, Some(TokenType::Ws), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word),
+    Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::Word), Some(TokenType::LongWord), Some(TokenType::Word),
+];
+
+fn class_of(ch: char) -> usize {
+    let code_point = ch as u32;
+    if code_point < 128 {
+        return ASCII_CLASSES[code_point as usize] as usize;
+    }
+    match CLASS_STARTS.binary_search(&code_point) {
+        Ok(class) => class,
+        Err(next_class) => next_class - 1,
+    }
+}
+
+/// Longest match at `start`: token type and end
+fn longest_match(text: &str, start: usize) -> Option<(TokenType, usize)> {
+    let mut state = START_STATE;
+    let mut best = None;
+    for (offset, ch) in text[start..].char_indices() {
+        state = TRANSITIONS[state * CLASS_COUNT + class_of(ch)] as usize;
+        if state == DEAD_STATE {
+            break;
+        }
+        if let Some(token_type) = ACCEPTS[state] {
+            best = Some((token_type, start + offset + ch.len_utf8()));
+        }
+    }
+    best
+}
+
+/// Lazily splits text into tokens, finishing with zero length `TokenType::End` token.
+/// Text, which doesn't match any token, is reported as `TokenType::Error` tokens
+pub fn tokenize<'a>(text: &'a str) -> Lexer<'a> {
+    Lexer { text, position: 0, is_end: false, lookahead: None }
+}
+
+pub struct Lexer<'a> {
+    text: &'a str,
+    position: usize,
+    is_end: bool,
+    lookahead: Option<(TokenType, usize)>,
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.is_end {
+            return None;
+        }
+        let start = self.position;
+        if start == self.text.len() {
+            self.is_end = true;
+            return Some(Token { token_type: TokenType::End, start, end: start });
+        }
+        let best = match self.lookahead.take() {
+            Some(best) => Some(best),
+            None => longest_match(self.text, start),
+        };
+        let (token_type, end) = match best {
+            Some(best) => best,
+            None => {
+                // error token lasts until some token matches
+                let mut end = next_char_end(self.text, start);
+                while end < self.text.len() {
+                    if let Some(best) = longest_match(self.text, end) {
+                        self.lookahead = Some(best);
+                        break;
+                    }
+                    end = next_char_end(self.text, end);
+                }
+                (TokenType::Error, end)
+            }
+        };
+        self.position = end;
+        Some(Token { token_type, start, end })
+    }
+}
+
+fn next_char_end(text: &str, offset: usize) -> usize {
+    offset + text[offset..].chars().next().map(|ch| ch.len_utf8()).unwrap_or(0)
+}